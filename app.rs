@@ -6,16 +6,26 @@ use clap::{Arg, App, ArgMatches};
 use config::{ConfigError, Config, File};
 use std::env;
 
+use crate::rqids::RqIdsCompression;
+
 const NIX_PASTELD_PATH: &str = ".pastel";
 const MAC_PASTELD_PATH: &str = "Library/Application Support/Pastel";
 const WIN_PASTELD_PATH: &str = "AppData\\Roaming\\Pastel";
 const DEFAULT_CONFIG_FILE: &str = "rqservice";
+const DEFAULT_WORK_DIR: &str = "rqfiles";
 
 #[derive(Debug, Default, Clone)]
 pub struct ServiceSettings {
     pub grpc_service: String,
     pub symbol_size: u16,
     pub redundancy_factor: u8,
+    pub max_jobs: usize,
+    pub max_queued_jobs: usize,
+    pub work_dir: String,
+    pub session_timeout: u64,
+    pub max_sessions: usize,
+    pub ids_signing_key: String,
+    pub rq_ids_compression: RqIdsCompression,
     pub pastel_path: String,
     pub config_path: String
 }
@@ -51,11 +61,25 @@ impl ServiceSettings {
         let grpc_service = ServiceSettings::find_setting(&cmd_args, &cfg, "grpc-service", "".to_string(), true);
         let symbol_size = ServiceSettings::find_setting(&cmd_args, &cfg, "symbol-size", "50000".to_string(), false).parse::<u16>().unwrap();
         let redundancy_factor = ServiceSettings::find_setting(&cmd_args, &cfg, "redundancy-factor", "12".to_string(), false).parse::<u8>().unwrap();
+        let max_jobs = ServiceSettings::find_setting(&cmd_args, &cfg, "max-jobs", "4".to_string(), false).parse::<usize>().unwrap();
+        let max_queued_jobs = ServiceSettings::find_setting(&cmd_args, &cfg, "max-queued-jobs", "16".to_string(), false).parse::<usize>().unwrap();
+        let work_dir = ServiceSettings::find_setting(&cmd_args, &cfg, "work-dir", format!("{}/{}", pastel_path, DEFAULT_WORK_DIR), false);
+        let session_timeout = ServiceSettings::find_setting(&cmd_args, &cfg, "session-timeout", "600".to_string(), false).parse::<u64>().unwrap();
+        let max_sessions = ServiceSettings::find_setting(&cmd_args, &cfg, "max-sessions", "64".to_string(), false).parse::<usize>().unwrap();
+        let ids_signing_key = ServiceSettings::find_setting(&cmd_args, &cfg, "ids-signing-key", "".to_string(), false);
+        let rq_ids_compression = ServiceSettings::find_setting(&cmd_args, &cfg, "rq-ids-compression", "none".to_string(), false).parse::<RqIdsCompression>().unwrap();
 
         Ok(ServiceSettings{
             grpc_service,
             symbol_size,
             redundancy_factor,
+            max_jobs,
+            max_queued_jobs,
+            work_dir,
+            session_timeout,
+            max_sessions,
+            ids_signing_key,
+            rq_ids_compression,
             pastel_path,
             config_path})
     }
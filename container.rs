@@ -0,0 +1,259 @@
+// Copyright (c) 2021-2021 The Pastel Core developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+use raptorq::ObjectTransmissionInformation;
+
+use std::convert::TryInto;
+use std::io::prelude::*;
+use std::io::{self, SeekFrom};
+
+use crate::encoder::check_config;
+
+pub const CONTAINER_EXTENSION: &str = "rqc";
+pub const CONTAINER_VERSION: u32 = 1;
+
+const CONTAINER_MAGIC: &[u8; 8] = b"RQSYMBLS";
+// Symbol ids and the file hash are base58 SHA3-256, stored as the 32 bytes of the hash
+const HASH_SIZE: usize = 32;
+// magic, version, encoder parameters, file hash, symbols count
+const INDEX_OFFSET: u64 = 8 + 4 + 12 + HASH_SIZE as u64 + 4;
+// symbol id, offset, length
+const INDEX_ENTRY_SIZE: u64 = HASH_SIZE as u64 + 8 + 4;
+
+/// Where a symbol is in the container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerEntry {
+    pub id: String,
+    pub offset: u64,
+    pub length: u32
+}
+
+/// Packs the symbols of an object into a single file:
+///
+/// | size        | content                                                |
+/// |-------------|--------------------------------------------------------|
+/// | 8           | magic `RQSYMBLS`                                       |
+/// | 4           | version, little endian like all numbers                |
+/// | 12          | encoder parameters, the serialized OTI                 |
+/// | 32          | SHA3-256 of the original file                          |
+/// | 4           | number of symbols N                                    |
+/// | N x 44      | index - SHA3-256 symbol id, 8 bytes offset, 4 bytes length |
+/// |             | the packets, one after another                         |
+///
+/// The number of symbols is known before encoding, so the index is reserved first
+/// and written once all packets are in.
+pub struct ContainerWriter<W: Write + Seek> {
+    output: W,
+    index: Vec<([u8; HASH_SIZE], u64, u32)>,
+    symbols_count: u32,
+    offset: u64
+}
+
+impl<W: Write + Seek> ContainerWriter<W> {
+
+    pub fn new(mut output: W, config: &ObjectTransmissionInformation, file_hash: &str, symbols_count: u32)
+        -> io::Result<Self> {
+
+        output.write_all(CONTAINER_MAGIC)?;
+        output.write_all(&CONTAINER_VERSION.to_le_bytes())?;
+        output.write_all(&config.serialize())?;
+        output.write_all(&decode_hash(file_hash)?)?;
+        output.write_all(&symbols_count.to_le_bytes())?;
+        let index_size = symbols_count as u64 * INDEX_ENTRY_SIZE;
+        io::copy(&mut io::repeat(0).take(index_size), &mut output)?;
+
+        Ok(ContainerWriter {
+            output,
+            index: Vec::with_capacity(symbols_count as usize),
+            symbols_count,
+            offset: INDEX_OFFSET + index_size
+        })
+    }
+
+    pub fn add_symbol(&mut self, id: &str, packet: &[u8]) -> io::Result<()> {
+
+        if self.index.len() as u32 == self.symbols_count {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("Container is full, it has room for {} symbols", self.symbols_count)));
+        }
+        self.output.write_all(packet)?;
+        self.index.push((decode_hash(id)?, self.offset, packet.len() as u32));
+        self.offset += packet.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the index, all the symbols the container was created for have to be added first
+    pub fn finish(mut self) -> io::Result<W> {
+
+        if self.index.len() as u32 != self.symbols_count {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("Container has {} of {} symbols", self.index.len(), self.symbols_count)));
+        }
+        self.output.seek(SeekFrom::Start(INDEX_OFFSET))?;
+        for (id, offset, length) in &self.index {
+            self.output.write_all(id)?;
+            self.output.write_all(&offset.to_le_bytes())?;
+            self.output.write_all(&length.to_le_bytes())?;
+        }
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+/// Reads the header and the index of a container, and its symbols on request
+pub struct ContainerReader<R: Read + Seek> {
+    source: R,
+    config: ObjectTransmissionInformation,
+    file_hash: String,
+    entries: Vec<ContainerEntry>
+}
+
+impl<R: Read + Seek> ContainerReader<R> {
+
+    pub fn new(mut source: R) -> io::Result<Self> {
+
+        let size = source.seek(SeekFrom::End(0))?;
+        source.seek(SeekFrom::Start(0))?;
+
+        let mut header = [0u8; INDEX_OFFSET as usize];
+        source.read_exact(&mut header).map_err(|_| invalid("Too short for a container"))?;
+        if &header[..8] != CONTAINER_MAGIC {
+            return Err(invalid("Not a symbols container"));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version > CONTAINER_VERSION {
+            return Err(invalid(&format!("Unsupported container version {}", version)));
+        }
+        let config = ObjectTransmissionInformation::deserialize(header[12..24].try_into().unwrap());
+        if let Err(err) = check_config(&config) {
+            return Err(invalid(&format!("Invalid encoder parameters - {}", err)));
+        }
+        let file_hash = bs58::encode(&header[24..24 + HASH_SIZE]).into_string();
+        let symbols_count = u32::from_le_bytes(header[24 + HASH_SIZE..].try_into().unwrap());
+
+        let index_size = symbols_count as u64 * INDEX_ENTRY_SIZE;
+        if INDEX_OFFSET + index_size > size {
+            return Err(invalid("Index is past the end of the container"));
+        }
+        let mut index = vec![0u8; index_size as usize];
+        source.read_exact(&mut index)?;
+
+        let mut entries = Vec::with_capacity(symbols_count as usize);
+        for entry in index.chunks(INDEX_ENTRY_SIZE as usize) {
+            let offset = u64::from_le_bytes(entry[HASH_SIZE..HASH_SIZE + 8].try_into().unwrap());
+            let length = u32::from_le_bytes(entry[HASH_SIZE + 8..].try_into().unwrap());
+            if offset.checked_add(length as u64).map_or(true, |end| end > size) {
+                return Err(invalid("Symbol is past the end of the container"));
+            }
+            entries.push(ContainerEntry {
+                id: bs58::encode(&entry[..HASH_SIZE]).into_string(),
+                offset,
+                length
+            });
+        }
+
+        Ok(ContainerReader {
+            source,
+            config,
+            file_hash,
+            entries
+        })
+    }
+
+    pub fn get_config(&self) -> ObjectTransmissionInformation {
+        self.config
+    }
+
+    pub fn file_hash(&self) -> &str {
+        &self.file_hash
+    }
+
+    pub fn entries(&self) -> &[ContainerEntry] {
+        &self.entries
+    }
+
+    /// Reads the packet of the `index`-th entry
+    pub fn read_symbol(&mut self, index: usize) -> io::Result<Vec<u8>> {
+
+        let entry = match self.entries.get(index) {
+            Some(entry) => entry,
+            None => return Err(invalid(&format!("No symbol {} in the container", index)))
+        };
+        let mut data = vec![0u8; entry.length as usize];
+        self.source.seek(SeekFrom::Start(entry.offset))?;
+        self.source.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Tells containers from anything else by their magic
+pub fn is_container<R: Read>(mut source: R) -> bool {
+    let mut magic = [0u8; 8];
+    source.read_exact(&mut magic).is_ok() && &magic == CONTAINER_MAGIC
+}
+
+fn decode_hash(hash: &str) -> io::Result<[u8; HASH_SIZE]> {
+    match bs58::decode(hash).into_vec() {
+        Ok(bytes) if bytes.len() == HASH_SIZE => Ok(bytes[..].try_into().unwrap()),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Not a base58 SHA3-256 hash: {}", hash)))
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha3::{Digest, Sha3_256};
+    use std::io::Cursor;
+
+    fn hash(data: &[u8]) -> String {
+        bs58::encode(&Sha3_256::digest(data)).into_string()
+    }
+
+    #[test]
+    fn write_and_read() {
+        let config = ObjectTransmissionInformation::with_defaults(10_000, 1_000);
+        let packets: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 1_004]).collect();
+
+        let mut writer = ContainerWriter::new(Cursor::new(Vec::new()), &config, &hash(b"file"), 5).unwrap();
+        for packet in &packets {
+            writer.add_symbol(&hash(packet), packet).unwrap();
+        }
+        assert!(writer.add_symbol(&hash(b"extra"), b"extra").is_err());
+        let data = writer.finish().unwrap().into_inner();
+        assert_eq!(data.len() as u64, INDEX_OFFSET + 5 * INDEX_ENTRY_SIZE + 5 * 1_004);
+        assert!(is_container(&data[..]));
+
+        let mut reader = ContainerReader::new(Cursor::new(&data)).unwrap();
+        assert_eq!(reader.get_config(), config);
+        assert_eq!(reader.file_hash(), hash(b"file"));
+        assert_eq!(reader.entries().len(), 5);
+        for (i, packet) in packets.iter().enumerate() {
+            assert_eq!(reader.entries()[i].id, hash(packet));
+            assert_eq!(&reader.read_symbol(i).unwrap(), packet);
+        }
+        assert!(reader.read_symbol(5).is_err());
+
+        // Zero symbol size in the header
+        let mut broken = data.clone();
+        broken[18..20].copy_from_slice(&[0, 0]);
+        assert!(ContainerReader::new(Cursor::new(&broken)).is_err());
+
+        // Cut in the middle of the packets
+        assert!(ContainerReader::new(Cursor::new(&data[..data.len() - 1])).is_err());
+        assert!(!is_container(&b"{\"version\":1}"[..]));
+    }
+
+    #[test]
+    fn missing_symbols() {
+        let config = ObjectTransmissionInformation::with_defaults(10_000, 1_000);
+        let mut writer = ContainerWriter::new(Cursor::new(Vec::new()), &config, &hash(b"file"), 2).unwrap();
+        writer.add_symbol(&hash(b"one"), b"one").unwrap();
+        assert!(writer.finish().is_err());
+        assert!(ContainerWriter::new(Cursor::new(Vec::new()), &config, "not a hash", 2).is_err());
+    }
+}
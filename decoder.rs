@@ -2,4 +2,259 @@
 // Distributed under the MIT software license, see the accompanying
 // file COPYING or http://www.opensource.org/licenses/mit-license.php.
 
-// use raptorq::{Decoder, Encoder, EncodingPacket};
+use raptorq::{extended_source_block_symbols, EncodingPacket, ObjectTransmissionInformation, SourceBlockDecoder};
+
+use std::collections::HashSet;
+use std::io::prelude::*;
+use std::io::{self, SeekFrom};
+use std::iter;
+
+use crate::encoder::{block_layout, SourceBlockLayout};
+
+// A block decodes from as many symbols as it has source symbols with a probability of about 99%,
+// and from two more with about 99.9999% (RFC 6330) - the estimates count on the latter
+const DECODING_OVERHEAD: u32 = 2;
+
+/// Estimates how many more symbols a block with `source_symbols` source symbols needs to decode,
+/// once it has `received` distinct valid symbols
+pub fn block_symbols_needed(source_symbols: u32, received: u32) -> u64 {
+    (source_symbols + DECODING_OVERHEAD).saturating_sub(received) as u64
+}
+
+/// Restores an object one source block at a time, writing every decoded
+/// block straight to its offset in `output`, so only a single block
+/// has to be kept in memory regardless of the object size.
+pub struct BlockDecoder<W: Write + Seek> {
+    output: W,
+    config: ObjectTransmissionInformation,
+    blocks: Vec<SourceBlockLayout>
+}
+
+impl<W: Write + Seek> BlockDecoder<W> {
+
+    pub fn new(output: W, config: ObjectTransmissionInformation) -> Self {
+
+        BlockDecoder {
+            output,
+            config,
+            blocks: block_layout(&config)
+        }
+    }
+
+    pub fn get_config(&self) -> ObjectTransmissionInformation {
+        self.config
+    }
+
+    pub fn blocks_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn source_block_decoder(&self, source_block_number: u8) -> SourceBlockDecoder {
+        let block = &self.blocks[source_block_number as usize];
+        SourceBlockDecoder::new2(source_block_number, &self.config, block.length)
+    }
+
+    /// Deserializes a packet of this object. Returns `None` when the data cannot be
+    /// a symbol of it - wrong size, unknown source block or an encoding symbol id
+    /// the block decoder doesn't accept.
+    pub fn parse_packet(&self, data: &[u8]) -> Option<EncodingPacket> {
+
+        if data.len() != 4 + self.config.symbol_size() as usize {
+            return None;
+        }
+        let packet = EncodingPacket::deserialize(data);
+
+        let block = self.blocks.get(packet.payload_id().source_block_number() as usize)?;
+        let source_symbols = (block.length / self.config.symbol_size() as u64) as u32;
+        let esi = packet.payload_id().encoding_symbol_id();
+        // Padding symbols between K and K' are never sent
+        if esi >= source_symbols && esi < extended_source_block_symbols(source_symbols) {
+            return None;
+        }
+        Some(packet)
+    }
+
+    /// Writes a decoded block at its offset in the output,
+    /// dropping the zero padding past the end of the object.
+    /// Returns the number of bytes written.
+    pub fn write_block(&mut self, source_block_number: u8, data: &[u8]) -> io::Result<usize> {
+
+        let block = &self.blocks[source_block_number as usize];
+        let len = std::cmp::min(data.len() as u64,
+                                self.config.transfer_length() - block.offset) as usize;
+
+        self.output.seek(SeekFrom::Start(block.offset))?;
+        self.output.write_all(&data[..len])?;
+        Ok(len)
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+/// What `StreamDecoder::add_packet` has done with a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketResult {
+    /// Not a symbol of the object
+    Rejected,
+    /// Already received, or its block is already restored
+    Redundant,
+    /// Kept, its block needs more symbols
+    Accepted,
+    /// Completed its block, which has been written to the output - the number of bytes written
+    BlockRestored(usize)
+}
+
+struct StreamBlock {
+    decoder: Option<SourceBlockDecoder>,
+    received: HashSet<u32>,
+    source_symbols: u32,
+    restored: bool
+}
+
+/// Restores an object from packets arriving in any order and over any length of time.
+/// Every source block is written to the output as soon as enough of its packets have arrived.
+pub struct StreamDecoder<W: Write + Seek> {
+    dec: BlockDecoder<W>,
+    blocks: Vec<StreamBlock>,
+    restored_blocks: usize
+}
+
+impl<W: Write + Seek> StreamDecoder<W> {
+
+    pub fn new(output: W, config: ObjectTransmissionInformation) -> Self {
+
+        let dec = BlockDecoder::new(output, config);
+        let blocks = dec.blocks.iter().map(|block| StreamBlock {
+            decoder: None,
+            received: HashSet::new(),
+            source_symbols: (block.length / config.symbol_size() as u64) as u32,
+            restored: false
+        }).collect();
+
+        StreamDecoder {
+            dec,
+            blocks,
+            restored_blocks: 0
+        }
+    }
+
+    pub fn add_packet(&mut self, data: &[u8]) -> io::Result<PacketResult> {
+
+        let packet = match self.dec.parse_packet(data) {
+            Some(packet) => packet,
+            None => return Ok(PacketResult::Rejected)
+        };
+        let sbn = packet.payload_id().source_block_number();
+        let block = &mut self.blocks[sbn as usize];
+        if block.restored || !block.received.insert(packet.payload_id().encoding_symbol_id()) {
+            return Ok(PacketResult::Redundant);
+        }
+
+        // Block decoders are only created once their first packet arrives
+        let dec = &self.dec;
+        let block_dec = block.decoder.get_or_insert_with(|| dec.source_block_decoder(sbn));
+        match block_dec.decode(iter::once(packet)) {
+            Some(result) => {
+                block.restored = true;
+                block.decoder = None;
+                block.received = HashSet::new();
+                self.restored_blocks += 1;
+                let len = self.dec.write_block(sbn, &result)?;
+                Ok(PacketResult::BlockRestored(len))
+            },
+            None => Ok(PacketResult::Accepted)
+        }
+    }
+
+    pub fn is_restored(&self) -> bool {
+        self.restored_blocks == self.blocks.len()
+    }
+
+    /// Estimates how many more symbols are needed to restore the object, see `block_symbols_needed`.
+    /// A block that is still not decoded needs at least one more.
+    pub fn symbols_needed(&self) -> u64 {
+        self.blocks.iter()
+            .filter(|block| !block.restored)
+            .map(|block| std::cmp::max(block_symbols_needed(block.source_symbols, block.received.len() as u32), 1))
+            .sum()
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.dec.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::BlockEncoder;
+    use std::io::Cursor;
+
+    #[test]
+    fn restore_from_repair_symbols() {
+        let data: Vec<u8> = (0..1_000_003u32).map(|i| (i * 31 % 251) as u8).collect();
+        let config = ObjectTransmissionInformation::new(data.len() as u64, 1_000, 3, 1, 8);
+
+        let mut enc = BlockEncoder::new(Cursor::new(&data), config);
+        let mut dec = BlockDecoder::new(Cursor::new(Vec::new()), config);
+        while let Some(block) = enc.next_block().unwrap() {
+            let mut packets = Vec::new();
+            block.for_each_packet(block.source_symbols() + 10, |packet| -> Result<(), ()> {
+                packets.push(packet);
+                Ok(())
+            }).unwrap();
+
+            // Skip the source symbols, so every block has to be really decoded
+            let sbn = packets[0].payload_id().source_block_number();
+            let mut block_dec = dec.source_block_decoder(sbn);
+            let restored = packets.into_iter()
+                .skip(block.source_symbols() as usize)
+                .find_map(|packet| block_dec.decode(iter::once(packet)))
+                .unwrap();
+            dec.write_block(sbn, &restored).unwrap();
+        }
+        assert_eq!(dec.into_inner().into_inner(), data);
+    }
+
+    #[test]
+    fn restore_from_stream() {
+        let data: Vec<u8> = (0..100_003u32).map(|i| (i * 17 % 253) as u8).collect();
+        let config = ObjectTransmissionInformation::new(data.len() as u64, 1_000, 2, 1, 8);
+
+        let mut enc = BlockEncoder::new(Cursor::new(&data), config);
+        let mut packets = Vec::new();
+        while let Some(block) = enc.next_block().unwrap() {
+            block.for_each_packet(block.source_symbols(), |packet| -> Result<(), ()> {
+                packets.push(packet.serialize());
+                Ok(())
+            }).unwrap();
+        }
+        // Repair symbols first, blocks interleaved
+        packets.reverse();
+
+        let mut dec = StreamDecoder::new(Cursor::new(Vec::new()), config);
+        assert_eq!(dec.symbols_needed(), 101 + 2 * DECODING_OVERHEAD as u64);
+        assert_eq!(dec.add_packet(&packets[0][..10]).unwrap(), PacketResult::Rejected);
+        assert_eq!(dec.add_packet(&packets[0]).unwrap(), PacketResult::Accepted);
+        assert_eq!(dec.add_packet(&packets[0]).unwrap(), PacketResult::Redundant);
+        assert_eq!(dec.symbols_needed(), 100 + 2 * DECODING_OVERHEAD as u64);
+
+        let mut restored = 0;
+        for packet in &packets[1..] {
+            if let PacketResult::BlockRestored(len) = dec.add_packet(packet).unwrap() {
+                restored += len;
+            }
+            if dec.is_restored() {
+                break;
+            }
+        }
+        assert!(dec.is_restored());
+        assert_eq!(dec.symbols_needed(), 0);
+        assert_eq!(restored, data.len());
+        assert_eq!(dec.into_inner().into_inner(), data);
+    }
+}
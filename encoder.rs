@@ -0,0 +1,235 @@
+// Copyright (c) 2021-2021 The Pastel Core developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+use raptorq::{partition, EncodingPacket, ObjectTransmissionInformation,
+              SourceBlockEncoder, SourceBlockEncodingPlan};
+
+use std::io::prelude::*;
+use std::io;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SourceBlockLayout {
+    pub source_block_number: u8,
+    pub offset: u64,
+    pub length: u64
+}
+
+/// Encodes an object read from `source` one source block at a time,
+/// so only a single block is kept in memory regardless of the object size.
+pub struct BlockEncoder<R: Read> {
+    source: R,
+    config: ObjectTransmissionInformation,
+    blocks: Vec<SourceBlockLayout>,
+    next_block: usize,
+    plan: Option<(u32, SourceBlockEncodingPlan)>
+}
+
+pub struct EncodedBlock {
+    encoder: SourceBlockEncoder,
+    source_symbols: u32
+}
+
+// Limits of raptorq and RFC 6330, section 4.3
+const MAX_TRANSFER_LENGTH: u64 = 942_574_504_275;
+const MAX_SOURCE_SYMBOLS_PER_BLOCK: u64 = 56_403;
+
+/// Checks that `config` describes an object `block_layout` and the block coders can handle.
+/// Deserialized parameters are not checked by raptorq, and zero or inconsistent fields make it panic.
+pub fn check_config(config: &ObjectTransmissionInformation) -> Result<(), String> {
+
+    let symbol_size = config.symbol_size() as u64;
+    if symbol_size == 0 || config.source_blocks() == 0 || config.sub_blocks() == 0 || config.symbol_alignment() == 0 {
+        return Err("symbol size, source blocks, sub-blocks and symbol alignment must not be zero".to_string());
+    }
+    if symbol_size % config.symbol_alignment() as u64 != 0 {
+        return Err(format!("symbol size {} is not a multiple of the symbol alignment {}",
+                           symbol_size, config.symbol_alignment()));
+    }
+    if config.transfer_length() == 0 || config.transfer_length() > MAX_TRANSFER_LENGTH {
+        return Err(format!("transfer length {} is out of range", config.transfer_length()));
+    }
+
+    let symbols = (config.transfer_length() + symbol_size - 1) / symbol_size;
+    let source_blocks = config.source_blocks() as u64;
+    if source_blocks > symbols {
+        return Err(format!("{} source blocks for {} symbols", source_blocks, symbols));
+    }
+    if (symbols + source_blocks - 1) / source_blocks > MAX_SOURCE_SYMBOLS_PER_BLOCK {
+        return Err(format!("more than {} symbols per source block", MAX_SOURCE_SYMBOLS_PER_BLOCK));
+    }
+    Ok(())
+}
+
+/// Splits the object described by `config` into source blocks, the same way `raptorq::Encoder` does.
+/// `length` is the padded block length, always a multiple of the symbol size.
+pub fn block_layout(config: &ObjectTransmissionInformation) -> Vec<SourceBlockLayout> {
+
+    let symbol_size = config.symbol_size() as u64;
+    let kt = (config.transfer_length() as f64 / symbol_size as f64).ceil() as u32;
+    let (kl, ks, zl, zs) = partition(kt, config.source_blocks());
+
+    let mut offset = 0u64;
+    (0..zl + zs).map(|sbn| {
+        let length = if sbn < zl { kl as u64 } else { ks as u64 } * symbol_size;
+        let block = SourceBlockLayout {
+            source_block_number: sbn as u8,
+            offset,
+            length
+        };
+        offset += length;
+        block
+    }).collect()
+}
+
+impl<R: Read> BlockEncoder<R> {
+
+    pub fn new(source: R, config: ObjectTransmissionInformation) -> Self {
+
+        BlockEncoder {
+            source,
+            config,
+            blocks: block_layout(&config),
+            next_block: 0,
+            plan: None
+        }
+    }
+
+    pub fn get_config(&self) -> ObjectTransmissionInformation {
+        self.config
+    }
+
+    pub fn blocks_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn source_symbols(&self) -> u32 {
+        let symbol_size = self.config.symbol_size() as u64;
+        self.blocks.iter().map(|block| (block.length / symbol_size) as u32).sum()
+    }
+
+    /// Number of bytes read from the source so far
+    pub fn bytes_read(&self) -> u64 {
+        match self.next_block.checked_sub(1).and_then(|last| self.blocks.get(last)) {
+            Some(block) => std::cmp::min(block.offset + block.length, self.config.transfer_length()),
+            None => 0
+        }
+    }
+
+    /// Reads the next source block from the source and prepares its encoder.
+    /// Returns `None` when all blocks have been read.
+    pub fn next_block(&mut self) -> io::Result<Option<EncodedBlock>> {
+
+        let block = match self.blocks.get(self.next_block) {
+            Some(block) => *block,
+            None => return Ok(None)
+        };
+        self.next_block += 1;
+
+        let data_len = std::cmp::min(block.length,
+                                     self.config.transfer_length() - block.offset);
+        let mut data = Vec::with_capacity(block.length as usize);
+        (&mut self.source).take(data_len).read_to_end(&mut data)?;
+        if (data.len() as u64) < data_len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                      "Source is shorter than the transfer length"));
+        }
+        // Zero pad the last symbol, see section 4.4.1.2
+        data.resize(block.length as usize, 0);
+
+        let source_symbols = (block.length / self.config.symbol_size() as u64) as u32;
+        // Blocks of one object have at most two different sizes, so the plan is mostly reused
+        let plan = match self.plan.take() {
+            Some((symbols, plan)) if symbols == source_symbols => plan,
+            _ => SourceBlockEncodingPlan::generate(source_symbols as u16)
+        };
+
+        let encoder = SourceBlockEncoder::with_encoding_plan2(block.source_block_number,
+                                                              &self.config,
+                                                              &data,
+                                                              &plan);
+        self.plan = Some((source_symbols, plan));
+
+        Ok(Some(EncodedBlock { encoder, source_symbols }))
+    }
+}
+
+impl EncodedBlock {
+
+    pub fn source_symbols(&self) -> u32 {
+        self.source_symbols
+    }
+
+    /// Calls `f` for the source packets of the block and then for `repair_symbols` repair packets.
+    /// Repair packets are generated in batches of the block size to keep memory bounded.
+    pub fn for_each_packet<F, E>(&self, repair_symbols: u32, mut f: F) -> Result<(), E>
+        where F: FnMut(EncodingPacket) -> Result<(), E> {
+
+        for packet in self.encoder.source_packets() {
+            f(packet)?;
+        }
+
+        let batch = std::cmp::max(self.source_symbols, 1);
+        let mut start = 0;
+        while start < repair_symbols {
+            let count = std::cmp::min(batch, repair_symbols - start);
+            for packet in self.encoder.repair_packets(start, count) {
+                f(packet)?;
+            }
+            start += count;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raptorq::Encoder;
+    use std::io::Cursor;
+
+    #[test]
+    fn same_packets_as_encoder() {
+        let data: Vec<u8> = (0..1_000_003u32).map(|i| (i * 31 % 251) as u8).collect();
+        // 1001 symbols split into blocks of two different sizes, the last one padded
+        let config = ObjectTransmissionInformation::new(data.len() as u64, 1_000, 3, 1, 8);
+
+        let expected = Encoder::new(&data, config).get_encoded_packets(15);
+
+        let mut enc = BlockEncoder::new(Cursor::new(&data), config);
+        let mut packets = Vec::new();
+        while let Some(block) = enc.next_block().unwrap() {
+            block.for_each_packet(15, |packet| -> Result<(), ()> {
+                packets.push(packet);
+                Ok(())
+            }).unwrap();
+        }
+        assert_eq!(packets, expected);
+    }
+
+    #[test]
+    fn invalid_configs() {
+        assert!(check_config(&ObjectTransmissionInformation::new(1_000_003, 1_000, 3, 1, 8)).is_ok());
+        assert!(check_config(&ObjectTransmissionInformation::with_defaults(10_000_000, 50_000)).is_ok());
+
+        let serialized = ObjectTransmissionInformation::new(1_000_003, 1_000, 3, 1, 8).serialize();
+        let with = |offset: usize, value: &[u8]| {
+            let mut data = serialized;
+            data[offset..offset + value.len()].copy_from_slice(value);
+            ObjectTransmissionInformation::deserialize(&data)
+        };
+        // transfer length, symbol size, source blocks, sub-blocks, symbol alignment
+        assert!(check_config(&with(0, &[0, 0, 0, 0, 0])).is_err());
+        assert!(check_config(&with(6, &[0, 0])).is_err());
+        assert!(check_config(&with(8, &[0])).is_err());
+        assert!(check_config(&with(9, &[0, 0])).is_err());
+        assert!(check_config(&with(11, &[0])).is_err());
+        assert!(check_config(&with(11, &[7])).is_err());
+        // 255 source blocks for 1_001 symbols
+        assert!(check_config(&with(8, &[255])).is_ok());
+        // More blocks than symbols: 3 source blocks for 1 symbol
+        assert!(check_config(&with(0, &[0, 0, 0, 0, 100])).is_err());
+        // Too many symbols for a block: 2^32 bytes in 3 source blocks
+        assert!(check_config(&with(0, &[0, 1, 0, 0, 0])).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright (c) 2021-2021 The Pastel Core developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::Status;
+use uuid::Uuid;
+
+use crate::rqprocessor::{JobControl, RqProcessorError};
+use crate::workers::WorkerPool;
+
+// Finished jobs are kept this long, so clients can still fetch their results
+const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JobResult {
+    pub paths: Vec<String>,
+    pub encoder_parameters: Vec<u8>,
+    pub symbols_count: u32,
+    pub file_hash: String,
+    pub rejected_symbols: Vec<String>
+}
+
+#[derive(Debug, Clone)]
+pub struct JobStatus {
+    pub kind: String,
+    pub state: JobState,
+    pub processed: u64,
+    pub total: u64,
+    pub result: Option<JobResult>,
+    pub error: String
+}
+
+#[derive(Debug)]
+struct Job {
+    kind: String,
+    state: JobState,
+    control: Arc<JobControl>,
+    result: Option<JobResult>,
+    error: String,
+    finished_at: Option<Instant>
+}
+
+/// Runs requests in the background on the `WorkerPool` and keeps their state,
+/// so clients can poll for the result or cancel them by job id.
+#[derive(Debug, Clone)]
+pub struct JobManager {
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+    workers: WorkerPool
+}
+
+impl JobManager {
+
+    pub fn new(workers: WorkerPool) -> Self {
+
+        JobManager {
+            jobs: Arc::new(Mutex::new(HashMap::new())),
+            workers
+        }
+    }
+
+    /// Queues `f` and returns the job id right away.
+    /// Fails with `RESOURCE_EXHAUSTED` when the worker pool queue is full.
+    pub fn submit<F>(&self, kind: &str, f: F) -> Result<String, Status>
+        where F: FnOnce(Arc<JobControl>) -> Result<JobResult, RqProcessorError> + Send + 'static {
+
+        let admission = self.workers.admit()?;
+        self.purge_finished();
+
+        let job_id = Uuid::new_v4().to_string();
+        let control = Arc::new(JobControl::default());
+        self.jobs.lock().unwrap().insert(job_id.clone(), Job {
+            kind: kind.to_string(),
+            state: JobState::Queued,
+            control: control.clone(),
+            result: None,
+            error: String::new(),
+            finished_at: None
+        });
+
+        let manager = self.clone();
+        let id = job_id.clone();
+        tokio::spawn(async move {
+            let job_manager = manager.clone();
+            let job_id = id.clone();
+            let result = admission.run(move || {
+                if !job_manager.start(&job_id) {
+                    return Err(RqProcessorError::new("submit", "Cancelled", "".to_string()));
+                }
+                f(control)
+            }).await;
+            manager.finish(&id, result);
+        });
+
+        Ok(job_id)
+    }
+
+    pub fn status(&self, job_id: &str) -> Option<JobStatus> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.get(job_id).map(JobManager::job_status)
+    }
+
+    /// Asks the job to stop. A queued job is cancelled at once,
+    /// a running one stops between symbols and removes its partial output.
+    pub fn cancel(&self, job_id: &str) -> Option<JobStatus> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(job_id)?;
+
+        job.control.cancel();
+        if job.state == JobState::Queued {
+            job.state = JobState::Cancelled;
+            job.finished_at = Some(Instant::now());
+        }
+        Some(JobManager::job_status(job))
+    }
+
+    // Marks the job as running unless it has been cancelled while queued.
+    // Both happen under the jobs lock, so a cancel can't be overwritten.
+    fn start(&self, job_id: &str) -> bool {
+        match self.jobs.lock().unwrap().get_mut(job_id) {
+            Some(job) if !job.control.is_cancelled() => {
+                job.state = JobState::Running;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn finish(&self, job_id: &str, result: Result<Result<JobResult, RqProcessorError>, Status>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = match jobs.get_mut(job_id) {
+            Some(job) => job,
+            None => return
+        };
+
+        match result {
+            Ok(Ok(result)) => {
+                job.state = JobState::Completed;
+                job.result = Some(result);
+            },
+            Ok(Err(err)) => {
+                if job.control.is_cancelled() {
+                    job.state = JobState::Cancelled;
+                } else {
+                    log::error!("Job {} failed: {:?}", job_id, err);
+                    job.state = JobState::Failed;
+                    job.error = err.to_string();
+                }
+            },
+            Err(status) => {
+                job.state = JobState::Failed;
+                job.error = status.message().to_string();
+            }
+        }
+        job.finished_at = Some(Instant::now());
+    }
+
+    fn purge_finished(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|_, job| match job.finished_at {
+            Some(finished_at) => finished_at.elapsed() < FINISHED_JOB_RETENTION,
+            None => true
+        });
+    }
+
+    fn job_status(job: &Job) -> JobStatus {
+        let (processed, total) = job.control.progress();
+        JobStatus {
+            kind: job.kind.clone(),
+            state: job.state,
+            processed,
+            total,
+            result: job.result.clone(),
+            error: job.error.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rqprocessor::{OutputOptions, RaptorQProcessor};
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+
+    async fn wait_finished(jobs: &JobManager, job_id: &str) -> JobStatus {
+        loop {
+            let status = jobs.status(job_id).unwrap();
+            if status.state != JobState::Queued && status.state != JobState::Running {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn wait_running(jobs: &JobManager, job_id: &str) {
+        while jobs.status(job_id).unwrap().state != JobState::Running {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn submit_and_complete() {
+        let jobs = JobManager::new(WorkerPool::new(1, 4));
+        let job_id = jobs.submit("test", |_control| {
+            Ok(JobResult { symbols_count: 3, ..Default::default() })
+        }).unwrap();
+
+        let status = wait_finished(&jobs, &job_id).await;
+        assert_eq!(status.kind, "test");
+        assert_eq!(status.state, JobState::Completed);
+        assert_eq!(status.result.unwrap().symbols_count, 3);
+        assert!(status.error.is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancel_queued_job() {
+        let jobs = JobManager::new(WorkerPool::new(1, 4));
+        let (tx, rx) = mpsc::channel::<()>();
+        let busy = jobs.submit("busy", move |_control| {
+            let _ = rx.recv();
+            Ok(JobResult::default())
+        }).unwrap();
+        wait_running(&jobs, &busy).await;
+
+        let started = Arc::new(AtomicBool::new(false));
+        let job_started = started.clone();
+        let queued = jobs.submit("queued", move |_control| {
+            job_started.store(true, Ordering::SeqCst);
+            Ok(JobResult::default())
+        }).unwrap();
+        assert_eq!(jobs.cancel(&queued).unwrap().state, JobState::Cancelled);
+
+        tx.send(()).unwrap();
+        assert_eq!(wait_finished(&jobs, &busy).await.state, JobState::Completed);
+        assert_eq!(wait_finished(&jobs, &queued).await.state, JobState::Cancelled);
+        assert!(!started.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn cancel_running_job() {
+        let output_path = Path::new("test/symbols/cancelled_job");
+        let _ = fs::remove_dir_all(output_path);
+
+        let jobs = JobManager::new(WorkerPool::new(1, 4));
+        let (tx, rx) = mpsc::channel::<()>();
+        let job_id = jobs.submit("encode", move |control| {
+            let _ = rx.recv();
+            let options = OutputOptions {
+                object_id: "cancelled_job".to_string(),
+                ..Default::default() };
+            let (meta, path) = RaptorQProcessor::new(50_000, 12)
+                .with_control(control)
+                .encode(&String::from("test/10_000_000"), &options)?;
+            Ok(JobResult { paths: vec![path], symbols_count: meta.source_symbols, ..Default::default() })
+        }).unwrap();
+        wait_running(&jobs, &job_id).await;
+
+        assert_eq!(jobs.cancel(&job_id).unwrap().state, JobState::Running);
+        tx.send(()).unwrap();
+        let status = wait_finished(&jobs, &job_id).await;
+        assert_eq!(status.state, JobState::Cancelled);
+        assert!(status.result.is_none());
+        assert!(!output_path.exists());
+    }
+
+    #[tokio::test]
+    async fn unknown_job() {
+        let jobs = JobManager::new(WorkerPool::new(1, 4));
+        assert!(jobs.status("unknown").is_none());
+        assert!(jobs.cancel("unknown").is_none());
+    }
+}
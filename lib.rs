@@ -0,0 +1,20 @@
+// Copyright (c) 2021-2021 The Pastel Core developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+pub mod app;
+pub mod container;
+pub mod decoder;
+pub mod encoder;
+pub mod jobs;
+pub mod manifest;
+pub mod rqids;
+pub mod rqserver;
+pub mod rqprocessor;
+pub mod sessions;
+pub mod uploads;
+pub mod workers;
+
+// What other tools need to load and check RQ IDs files
+pub use rqids::{RqIdsCompression, RqIdsFile, SignedRqIds};
+pub use rqprocessor::RqProcessorError;
@@ -4,9 +4,7 @@
 
 use flexi_logger::{Logger, FileSpec, WriteMode};
 
-pub mod app;
-pub mod rqserver;
-pub mod rqprocessor;
+use rq_service::{app, rqserver};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
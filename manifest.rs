@@ -0,0 +1,79 @@
+// Copyright (c) 2021-2021 The Pastel Core developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+use raptorq::{EncodingPacket, ObjectTransmissionInformation};
+use serde_derive::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_EXTENSION: &str = "manifest.json";
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// The manifest of the symbols at `symbols_path` is written next to their directory,
+/// as `<dir>.manifest.json`, so the directory only holds symbol files
+pub fn manifest_path(symbols_path: &Path) -> PathBuf {
+    let mut name = symbols_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(MANIFEST_EXTENSION);
+    symbols_path.with_file_name(name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolRole {
+    Source,
+    Repair
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestSymbol {
+    pub id: String,
+    pub source_block_number: u8,
+    pub encoding_symbol_id: u32,
+    pub size: u32,
+    pub role: SymbolRole
+}
+
+/// Describes the symbols written by `encode`, so the object can be restored
+/// and checked without keeping the encoder parameters anywhere else.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolsManifest {
+    pub version: u32,
+    pub encoder_parameters: Vec<u8>,
+    pub file_size: u64,
+    pub file_hash: String,
+    pub symbols: Vec<ManifestSymbol>
+}
+
+impl SymbolsManifest {
+
+    pub fn new(config: &ObjectTransmissionInformation, file_hash: &str) -> Self {
+
+        SymbolsManifest {
+            version: MANIFEST_VERSION,
+            encoder_parameters: config.serialize().to_vec(),
+            file_size: config.transfer_length(),
+            file_hash: file_hash.to_string(),
+            symbols: Vec::new()
+        }
+    }
+
+    /// `source_symbols` is the number of source symbols in the packet's block
+    pub fn add_symbol(&mut self, id: &str, packet: &EncodingPacket, source_symbols: u32) {
+
+        let payload_id = packet.payload_id();
+        let role = if payload_id.encoding_symbol_id() < source_symbols {
+            SymbolRole::Source
+        } else {
+            SymbolRole::Repair
+        };
+
+        self.symbols.push(ManifestSymbol {
+            id: id.to_string(),
+            source_block_number: payload_id.source_block_number(),
+            encoding_symbol_id: payload_id.encoding_symbol_id(),
+            size: packet.data().len() as u32,
+            role
+        });
+    }
+}
@@ -0,0 +1,362 @@
+// Copyright (c) 2021-2021 The Pastel Core developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use raptorq::ObjectTransmissionInformation;
+use serde_derive::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+
+use std::fs;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::rqprocessor::{RaptorQProcessor, RqProcessorError};
+
+pub const RQ_IDS_VERSION: u32 = 1;
+
+// Symbol ids are the base58 SHA3-256 of the symbol
+const SYMBOL_ID_SIZE: usize = 32;
+
+// Compressed ids files start with one of these, followed by the compressed JSON
+const ZSTD_MARKER: &[u8] = b"rqids+zstd\n";
+const GZIP_MARKER: &[u8] = b"rqids+gzip\n";
+
+/// How the JSON ids files are compressed. Pastel ids files are always zstd-compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RqIdsCompression {
+    None,
+    Zstd,
+    Gzip
+}
+
+impl Default for RqIdsCompression {
+    fn default() -> Self {
+        RqIdsCompression::None
+    }
+}
+
+impl FromStr for RqIdsCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(RqIdsCompression::None),
+            "zstd" => Ok(RqIdsCompression::Zstd),
+            "gzip" => Ok(RqIdsCompression::Gzip),
+            _ => Err(format!("Unknown RQ IDs compression '{}', expected none, zstd or gzip", s))
+        }
+    }
+}
+
+impl RqIdsCompression {
+
+    /// Compresses `data` and puts the format marker in front of it
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, RqProcessorError> {
+        match self {
+            RqIdsCompression::None => Ok(data.to_vec()),
+            RqIdsCompression::Zstd => {
+                let mut compressed = ZSTD_MARKER.to_vec();
+                zstd::stream::copy_encode(data, &mut compressed, 0)?;
+                Ok(compressed)
+            },
+            RqIdsCompression::Gzip => {
+                let mut encoder = GzEncoder::new(GZIP_MARKER.to_vec(), Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+
+    /// Decompresses ids files written with a format marker, anything else is returned as it is
+    pub fn decompress(data: &[u8]) -> Result<Vec<u8>, RqProcessorError> {
+        if let Some(compressed) = data.strip_prefix(ZSTD_MARKER) {
+            Ok(zstd::decode_all(compressed)?)
+        } else if let Some(compressed) = data.strip_prefix(GZIP_MARKER) {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(compressed).read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        } else {
+            Ok(data.to_vec())
+        }
+    }
+}
+
+/// Lists the symbols of an object, so nodes can check they store the right ones.
+/// Files written before the version was added read as version 0 and have none of the optional fields.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RqIdsFile {
+    #[serde(default, skip_serializing_if = "is_unversioned")]
+    pub version: u32,
+    pub id: String,
+    pub block_hash: String,
+    pub pastel_id: String,
+    pub symbol_identifiers: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub file_hash: String,
+    /// Name of the original file, without its directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_size: Option<u64>,
+    /// Serialized OTI the symbols were encoded with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder_parameters: Option<Vec<u8>>,
+    /// base64 signature of the canonical content
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String
+}
+
+/// An RQ IDs file with the signature it carries and the content the signature is for
+#[derive(Debug, Clone)]
+pub struct SignedRqIds {
+    pub rq_ids: RqIdsFile,
+    pub signed_content: Vec<u8>,
+    /// Empty when the file is not signed
+    pub signature: Vec<u8>,
+    /// Counter of a Pastel ids file
+    pub counter: Option<u32>
+}
+
+fn is_unversioned(version: &u32) -> bool {
+    *version == 0
+}
+
+impl RqIdsFile {
+
+    pub fn new(config: &ObjectTransmissionInformation, file_hash: &str, block_hash: &str, pastel_id: &str) -> Self {
+
+        RqIdsFile {
+            version: RQ_IDS_VERSION,
+            id: String::new(),
+            block_hash: block_hash.to_string(),
+            pastel_id: pastel_id.to_string(),
+            symbol_identifiers: Vec::new(),
+            file_hash: file_hash.to_string(),
+            file_name: None,
+            file_size: Some(config.transfer_length()),
+            encoder_parameters: Some(config.serialize().to_vec()),
+            signature: String::new()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<RqIdsFile, RqProcessorError> {
+        Ok(RqIdsFile::load_signed(path)?.rq_ids)
+    }
+
+    pub fn load_signed(path: &Path) -> Result<SignedRqIds, RqProcessorError> {
+
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) => {
+                return Err(RqProcessorError::new_file_err("load_rq_ids",
+                                                          "Cannot read RQ IDs file",
+                                                          path,
+                                                          err.to_string()));
+            }
+        };
+        RqIdsFile::parse_signed(&data).map_err(|err| {
+            RqProcessorError::new_file_err("load_rq_ids", "Invalid RQ IDs file", path, err.to_string())
+        })
+    }
+
+    /// Reads the JSON ids files, compressed or not, and the Pastel ids files, and validates them
+    pub fn parse(data: &[u8]) -> Result<RqIdsFile, RqProcessorError> {
+        Ok(RqIdsFile::parse_signed(data)?.rq_ids)
+    }
+
+    pub fn parse_signed(data: &[u8]) -> Result<SignedRqIds, RqProcessorError> {
+
+        let data = &RqIdsCompression::decompress(data)?[..];
+        let signed = match serde_json::from_slice::<RqIdsFile>(data) {
+            Ok(rq_ids) => {
+                // The signature is checked against the known fields only, so a signed file
+                // must not carry anything else - unknown fields would go unchecked
+                if !rq_ids.signature.is_empty() &&
+                    serde_json::from_slice::<serde_json::Value>(data)? != serde_json::to_value(&rq_ids)? {
+                    return Err(RqProcessorError::new("parse_rq_ids",
+                                                     "Signed RQ IDs file has fields outside the signed content",
+                                                     "".to_string()));
+                }
+                let signed_content = rq_ids.canonical_content()?.into_bytes();
+                let signature = decode_base64(&rq_ids.signature)?;
+                SignedRqIds { rq_ids, signed_content, signature, counter: None }
+            },
+            Err(_) => parse_pastel_ids(data)?
+        };
+        signed.rq_ids.validate()?;
+        Ok(signed)
+    }
+
+    pub fn validate(&self) -> Result<(), RqProcessorError> {
+
+        if self.version > RQ_IDS_VERSION {
+            return Err(RqProcessorError::new("validate_rq_ids",
+                                             "Unsupported RQ IDs file version",
+                                             format!("version {}", self.version)));
+        }
+        if self.symbol_identifiers.is_empty() {
+            return Err(RqProcessorError::new("validate_rq_ids", "No symbol identifiers", "".to_string()));
+        }
+        if let Some(id) = self.symbol_identifiers.iter().find(|id| !is_symbol_id(id)) {
+            return Err(RqProcessorError::new("validate_rq_ids", "Invalid symbol identifier", id.clone()));
+        }
+        if let Some(encoder_parameters) = &self.encoder_parameters {
+            let config = RaptorQProcessor::parse_encoder_parameters("validate_rq_ids", encoder_parameters)?;
+            if self.file_size.map_or(false, |size| size != config.transfer_length()) {
+                return Err(RqProcessorError::new("validate_rq_ids",
+                                                 "file_size doesn't match encoder_parameters",
+                                                 format!("{:?} != {}", self.file_size, config.transfer_length())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Id of the `index`-th ids file of an object, derived from the block hash, the pastel id,
+    /// the hash of the canonical content and the index - the same inputs always give the same id
+    pub fn deterministic_id(&self, index: u32) -> Result<String, RqProcessorError> {
+
+        let content_hash = Sha3_256::digest(self.canonical_content()?.as_bytes());
+        let seed = serde_json::to_vec(&(&self.block_hash, &self.pastel_id,
+                                        bs58::encode(&content_hash).into_string(), index))?;
+        Ok(bs58::encode(&Sha3_256::digest(&seed)).into_string())
+    }
+
+    /// The file as JSON without its id and signature, the same for all ids files of an object
+    pub fn canonical_content(&self) -> Result<String, RqProcessorError> {
+        let unsigned = RqIdsFile {
+            id: String::new(),
+            signature: String::new(),
+            ..self.clone()
+        };
+        Ok(serde_json::to_string(&unsigned)?)
+    }
+}
+
+fn is_symbol_id(id: &str) -> bool {
+    matches!(bs58::decode(id).into_vec(), Ok(hash) if hash.len() == SYMBOL_ID_SIZE)
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>, RqProcessorError> {
+    base64::decode(data).map_err(|err| {
+        RqProcessorError::new("parse_rq_ids", "Invalid base64 in RQ IDs file", err.to_string())
+    })
+}
+
+// Pastel ids files are `base64(JSON).base64(signature).counter`, zstd-compressed.
+// The signature is of the base64 encoded JSON.
+fn parse_pastel_ids(data: &[u8]) -> Result<SignedRqIds, RqProcessorError> {
+
+    let invalid = |err: String| RqProcessorError::new("parse_rq_ids", "Neither an RQ IDs JSON nor a Pastel ids file", err);
+
+    let content = zstd::decode_all(data).map_err(|err| invalid(err.to_string()))?;
+    let content = String::from_utf8(content).map_err(|err| invalid(err.to_string()))?;
+    let parts: Vec<&str> = content.split('.').collect();
+    if parts.len() != 3 {
+        return Err(invalid(format!("Expected 3 parts, got {}", parts.len())));
+    }
+    let json = decode_base64(parts[0])?;
+    let rq_ids = serde_json::from_slice::<RqIdsFile>(&json).map_err(|err| invalid(err.to_string()))?;
+    let signature = decode_base64(parts[1])?;
+    let counter = parts[2].parse::<u32>().map_err(|err| invalid(err.to_string()))?;
+
+    Ok(SignedRqIds {
+        rq_ids,
+        signed_content: parts[0].as_bytes().to_vec(),
+        signature,
+        counter: Some(counter)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOL_ID: &str = "8AJuyQDYRqu6fkJdXhHzwUuJ8yeSsoM5mGXTiX7kXAkP";
+
+    #[test]
+    fn parse_unversioned_file() {
+        let data = format!(r#"{{"id":"a","block_hash":"b","pastel_id":"c","symbol_identifiers":["{}"]}}"#, SYMBOL_ID);
+        let rq_ids = RqIdsFile::parse(data.as_bytes()).unwrap();
+        assert_eq!(rq_ids.version, 0);
+        assert_eq!(rq_ids.symbol_identifiers, vec![SYMBOL_ID.to_string()]);
+        assert_eq!(rq_ids.file_hash, "");
+        assert_eq!(rq_ids.file_size, None);
+        // Unversioned files keep their content when written back
+        assert_eq!(serde_json::to_string(&rq_ids).unwrap(), data);
+    }
+
+    #[test]
+    fn parse_and_validate() {
+        let config = ObjectTransmissionInformation::with_defaults(10_000, 1_000);
+        let mut rq_ids = RqIdsFile::new(&config, "hash", "b", "c");
+        rq_ids.file_name = Some("file".to_string());
+        rq_ids.symbol_identifiers.push(SYMBOL_ID.to_string());
+        let parsed = RqIdsFile::parse(&serde_json::to_vec(&rq_ids).unwrap()).unwrap();
+        assert_eq!(parsed, rq_ids);
+
+        let mut invalid = rq_ids.clone();
+        invalid.version = RQ_IDS_VERSION + 1;
+        assert!(invalid.validate().is_err());
+        let mut invalid = rq_ids.clone();
+        invalid.symbol_identifiers.push("not a symbol id".to_string());
+        assert!(invalid.validate().is_err());
+        let mut invalid = rq_ids.clone();
+        invalid.file_size = Some(10_001);
+        assert!(invalid.validate().is_err());
+
+        assert!(RqIdsFile::parse(b"{}").is_err());
+    }
+
+    #[test]
+    fn deterministic_ids() {
+        let config = ObjectTransmissionInformation::with_defaults(10_000, 1_000);
+        let mut rq_ids = RqIdsFile::new(&config, "hash", "b", "c");
+        rq_ids.symbol_identifiers.push(SYMBOL_ID.to_string());
+        let id = rq_ids.deterministic_id(0).unwrap();
+        assert_eq!(rq_ids.deterministic_id(0).unwrap(), id);
+        assert_ne!(rq_ids.deterministic_id(1).unwrap(), id);
+
+        // Ids and signatures are not part of the canonical content
+        rq_ids.id = id.clone();
+        rq_ids.signature = "c2lnbmF0dXJl".to_string();
+        assert_eq!(rq_ids.deterministic_id(0).unwrap(), id);
+        rq_ids.pastel_id = "d".to_string();
+        assert_ne!(rq_ids.deterministic_id(0).unwrap(), id);
+    }
+
+    #[test]
+    fn signed_file_without_unknown_fields() {
+        let config = ObjectTransmissionInformation::with_defaults(10_000, 1_000);
+        let mut rq_ids = RqIdsFile::new(&config, "hash", "b", "c");
+        rq_ids.symbol_identifiers.push(SYMBOL_ID.to_string());
+        let unsigned = serde_json::to_string(&rq_ids).unwrap().replacen('{', r#"{"extra":1,"#, 1);
+        assert!(RqIdsFile::parse(unsigned.as_bytes()).is_ok());
+
+        rq_ids.signature = "c2lnbmF0dXJl".to_string();
+        let data = serde_json::to_string(&rq_ids).unwrap();
+        assert_eq!(RqIdsFile::parse_signed(data.as_bytes()).unwrap().signature, b"signature");
+        let extra = data.replacen('{', r#"{"extra":1,"#, 1);
+        assert!(RqIdsFile::parse_signed(extra.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_compressed() {
+        let config = ObjectTransmissionInformation::with_defaults(10_000, 1_000);
+        let mut rq_ids = RqIdsFile::new(&config, "hash", "b", "c");
+        rq_ids.symbol_identifiers = vec![SYMBOL_ID.to_string(); 100];
+        let data = serde_json::to_vec(&rq_ids).unwrap();
+
+        for compression in &[RqIdsCompression::Zstd, RqIdsCompression::Gzip] {
+            let compressed = compression.compress(&data).unwrap();
+            assert!(compressed.len() < data.len());
+            assert_eq!(RqIdsFile::parse(&compressed).unwrap(), rq_ids);
+        }
+        assert_eq!(RqIdsCompression::None.compress(&data).unwrap(), data);
+        assert_eq!("gzip".parse::<RqIdsCompression>(), Ok(RqIdsCompression::Gzip));
+        assert!("lzma".parse::<RqIdsCompression>().is_err());
+    }
+}
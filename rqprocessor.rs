@@ -2,44 +2,348 @@
 // Distributed under the MIT software license, see the accompanying
 // file COPYING or http://www.opensource.org/licenses/mit-license.php.
 
-use raptorq::{Decoder, Encoder, EncodingPacket, ObjectTransmissionInformation};
+use raptorq::{ObjectTransmissionInformation, PayloadId};
 use sha3::{Digest, Sha3_256};
-use itertools::{Itertools};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer, Verifier};
 
 use std::io::prelude::*;
 use std::path::Path;
 use std::path::PathBuf;
+use std::path::Component;
 use std::fs::File;
-use std::{fs, fmt, io};
+use std::{fs, fmt, io, iter};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use uuid::Uuid;
-use serde_derive::{Deserialize, Serialize};
+
+use crate::container::{is_container, ContainerEntry, ContainerReader, ContainerWriter, CONTAINER_EXTENSION};
+use crate::decoder::{block_symbols_needed, BlockDecoder, PacketResult, StreamDecoder};
+use crate::encoder::{block_layout, check_config, BlockEncoder, EncodedBlock};
+use crate::manifest::{manifest_path, SymbolsManifest, MANIFEST_VERSION};
+use crate::rqids::{RqIdsCompression, RqIdsFile};
 
 #[derive(Debug, Clone)]
 pub struct RaptorQProcessor {
     symbol_size: u16,
     redundancy_factor: u8,
+    control: Option<Arc<JobControl>>,
+    progress: Option<ProgressCallback>,
+    rq_ids_mode: RqIdsMode,
+    rq_ids_compression: RqIdsCompression,
+    deterministic_rq_ids: bool,
+    // Name of the original file in the RQ IDs files, `None` for the name of the input file
+    rq_ids_file_name: Option<Option<String>>,
+    // Where temporary files go, the system temporary directory when it is not set
+    work_dir: Option<PathBuf>,
+}
+
+// Pastel compresses ids files with the highest zstd level
+const PASTEL_IDS_COMPRESSION_LEVEL: i32 = 22;
+// Work dir subdirectory of the files restored by `repair_symbols`
+const REPAIR_DIR: &str = "repair";
+
+/// How `create_metadata` names and writes the RQ IDs files
+#[derive(Debug, Clone)]
+pub enum RqIdsMode {
+    /// JSON files named after a random uuid, which is also their `id`.
+    /// With a signer, the files carry the signature of their canonical content.
+    Uuid { signer: Option<RqIdsSigner> },
+    /// Pastel protocol ids files - `base64(JSON).base64(signature).counter`, zstd-compressed
+    /// and named after the base58 SHA3-256 of the compressed content.
+    /// Every file gets the next counter, starting with `initial_counter`.
+    Pastel { signer: RqIdsSigner, initial_counter: u32 },
+}
+
+/// Signs the ids content once it is generated - with a local key, or by a hook.
+/// The signed content is the canonical ids JSON, base64 encoded for Pastel ids files.
+#[derive(Clone)]
+pub enum RqIdsSigner {
+    Hook(Arc<dyn Fn(&[u8]) -> Result<Vec<u8>, RqProcessorError> + Send + Sync>),
+}
+
+impl RqIdsSigner {
+    /// Signs with the Ed25519 key in `key_path` - the 32 bytes of the secret key
+    pub fn from_key_file(key_path: &String) -> Result<RqIdsSigner, RqProcessorError> {
+
+        let mut data = Vec::new();
+        RaptorQProcessor::open_and_read("from_key_file", &PathBuf::from(key_path), &mut data)?;
+        let secret = match SecretKey::from_bytes(&data) {
+            Ok(secret) => secret,
+            Err(err) => {
+                return Err(RqProcessorError::new_file_err("from_key_file",
+                                                          "Invalid Ed25519 secret key",
+                                                          Path::new(key_path),
+                                                          err.to_string()));
+            }
+        };
+        let public = PublicKey::from(&secret);
+        let keypair = Keypair { secret, public };
+        Ok(RqIdsSigner::Hook(Arc::new(move |data| Ok(keypair.sign(data).to_bytes().to_vec()))))
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>, RqProcessorError> {
+        match self {
+            RqIdsSigner::Hook(f) => f(data)
+        }
+    }
+}
+
+impl fmt::Debug for RqIdsSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RqIdsSigner::Hook(_) => write!(f, "Hook")
+        }
+    }
+}
+
+/// Progress of a running `encode` or `decode`, all counters are totals so far
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// Bytes of the input file read by the encoder
+    BytesRead { read: u64, total: u64 },
+    /// Symbols produced by the encoder
+    SymbolsWritten { written: u64, total: u64 },
+    /// Symbol files passed to the decoder, out of all files found at the symbols location
+    SymbolsFed { fed: u64, total: u64 },
+    /// Bytes of the original file restored by the decoder
+    BytesRestored { restored: u64, total: u64 },
+}
+
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(ProgressEvent) + Send + Sync>);
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProgressCallback")
+    }
+}
+
+/// Lets the caller follow the progress of a running request and stop it.
+/// Progress is counted in symbols for encoding and in restored bytes for decoding.
+#[derive(Debug, Default)]
+pub struct JobControl {
+    cancelled: AtomicBool,
+    processed: AtomicU64,
+    total: AtomicU64
+}
+
+impl JobControl {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+    /// Returns (processed, total)
+    pub fn progress(&self) -> (u64, u64) {
+        (self.processed.load(Ordering::SeqCst), self.total.load(Ordering::SeqCst))
+    }
+}
+
+type SymbolSink<'a> = &'a mut dyn FnMut(&str, PayloadId, Vec<u8>) -> Result<(), RqProcessorError>;
+
+// Removes the file at its path once it is dropped, whether the request succeeded, failed or panicked
+struct TemporaryFile(PathBuf);
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// Counters behind the `decode` progress events
+struct DecodeProgress {
+    fed: u64,
+    total: u64,
+    restored: u64
+}
+
+/// Restores a file from serialized packets added over any number of `add_packets` calls,
+/// made by `RaptorQProcessor::packets_decoder`. The output is written to a `.partial` file,
+/// which is removed when the decoder is dropped before the file is restored.
+pub struct PacketsDecoder {
+    processor: RaptorQProcessor,
+    config: ObjectTransmissionInformation,
+    dec: Option<StreamDecoder<File>>,
+    partial_file: PathBuf,
+    rest_file: PathBuf,
+    rest_file_str: String,
+    expected_hash: String,
+    received: u32,
+    rejected: u32,
+    restored: u64,
+    finished: bool
+}
+
+impl PacketsDecoder {
+
+    /// Takes packets until the file is restored, the rest of `packets` is not read.
+    /// Returns whether the file is restored.
+    pub fn add_packets<I>(&mut self, packets: I) -> Result<bool, RqProcessorError>
+        where I: IntoIterator<Item = Vec<u8>> {
+
+        let dec = match self.dec.as_mut() {
+            Some(dec) if !dec.is_restored() => dec,
+            _ => return Ok(true)
+        };
+        for data in packets {
+            self.processor.check_cancelled("decode_packets")?;
+
+            self.received += 1;
+            match dec.add_packet(&data) {
+                Ok(PacketResult::Rejected) => self.rejected += 1,
+                Ok(PacketResult::BlockRestored(len)) => {
+                    self.processor.add_progress(len as u64);
+                    self.restored += len as u64;
+                    self.processor.report(ProgressEvent::BytesRestored {
+                        restored: self.restored,
+                        total: self.config.transfer_length() });
+                },
+                Ok(_) => {},
+                Err(err) => {
+                    return Err(RqProcessorError::new_file_err("decode_packets",
+                                                              "Cannot write into the file",
+                                                              self.partial_file.as_path(),
+                                                              err.to_string()));
+                }
+            }
+            if dec.is_restored() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Moves the restored file into place once its hash is checked.
+    /// Returns the restored file location, the number of packets taken and the number of rejected ones.
+    pub fn finish(mut self) -> Result<(String, u32, u32), RqProcessorError> {
+
+        let symbols_needed = match self.dec.take() {
+            Some(dec) if !dec.is_restored() => dec.symbols_needed(),
+            _ => 0
+        };
+        if symbols_needed > 0 {
+            return Err(RqProcessorError::new("decode_packets",
+                                             "Cannot restore the original file from received symbols",
+                                             format!("{} more symbols are needed ({} of {} rejected)",
+                                                     symbols_needed, self.rejected, self.received)));
+        }
+
+        // Blocks may be restored in any order, so the file is hashed once it is complete
+        let restored_hash = RaptorQProcessor::file_hash("decode_packets", &self.partial_file)?;
+        RaptorQProcessor::move_into_place("decode_packets", &self.partial_file, &self.rest_file,
+                                          &restored_hash, &self.expected_hash)?;
+        self.finished = true;
+
+        Ok((self.rest_file_str.clone(), self.received, self.rejected))
+    }
+}
+
+impl Drop for PacketsDecoder {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.dec = None;
+            let _ = fs::remove_file(&self.partial_file);
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct EncoderMetaData {
     pub encoder_parameters: Vec<u8>,
     pub source_symbols: u32,
-    pub repair_symbols: u32
+    pub repair_symbols: u32,
+    pub file_hash: String,
+    /// Names of the RQ IDs files written
+    pub rq_ids: Vec<String>,
+    /// First and last counter used for Pastel ids files
+    pub rq_ids_counters: Option<(u32, u32)>
+}
+
+/// Where and how the results of a request are written.
+/// Empty strings mean the defaults derived from the input path.
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    pub object_id: String,
+    pub output_path: String,
+    pub overwrite: bool,
+    /// Pack the symbols into a single container file instead of a file per symbol
+    pub container: bool
+}
+
+// Where `encode_symbols` writes the symbols
+#[derive(Clone, Copy)]
+enum SymbolsOutput<'a> {
+    /// A file per symbol in this directory, and the manifest next to it
+    Files(&'a PathBuf),
+    Container(&'a PathBuf)
+}
+
+/// Differences between an RQ IDs file and the one the processor would create for a file
+#[derive(Debug, Clone, Default)]
+pub struct MetadataVerification {
+    pub symbols_match: bool,
+    pub block_hash_matches: bool,
+    pub pastel_id_matches: bool,
+    /// Symbols of the file the RQ IDs file doesn't list
+    pub missing_symbols: Vec<String>,
+    /// Symbols listed by the RQ IDs file the file doesn't have
+    pub unexpected_symbols: Vec<String>
+}
+
+/// Header and index of a symbols container
+#[derive(Debug, Clone, Default)]
+pub struct ContainerListing {
+    pub encoder_parameters: Vec<u8>,
+    pub file_hash: String,
+    pub symbols: Vec<ContainerEntry>
+}
+
+/// Content of an RQ IDs file and whether its signature is valid for the given public key
+#[derive(Debug, Clone, Default)]
+pub struct IdsFileVerification {
+    pub signed: bool,
+    pub signature_valid: bool,
+    pub block_hash: String,
+    pub pastel_id: String,
+    pub file_hash: String,
+    pub symbols_count: u32,
+    /// Counter of a Pastel ids file
+    pub counter: Option<u32>
+}
+
+/// State of a symbols directory compared with the symbols list of the object
+#[derive(Debug, Clone, Default)]
+pub struct SymbolsCheck {
+    /// Listed symbols present with the right content
+    pub valid: Vec<String>,
+    /// Listed symbols without a file
+    pub missing: Vec<String>,
+    /// Files whose content doesn't match their name
+    pub corrupted: Vec<String>,
+    /// Valid symbol files the list doesn't have
+    pub unlisted: Vec<String>,
+    /// How many more symbols are probably needed to decode, 0 when the valid ones are enough
+    pub symbols_needed: u64
+}
+
+/// What went wrong, as far as the caller can do something about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RqErrorKind {
+    Internal,
+    /// The restored file doesn't match the hash of the original file
+    HashMismatch
 }
 
 #[derive(Debug, Clone)]
 pub struct RqProcessorError {
     func: String,
     msg: String,
-    prev_msg: String
-}
-
-#[derive(Serialize, Deserialize)]
-struct RqIdsFile {
-    id: String,
-    block_hash: String,
-    pastel_id: String,
-    symbol_identifiers: Vec<String>
+    prev_msg: String,
+    kind: RqErrorKind
 }
 
 impl RqProcessorError {
@@ -47,16 +351,24 @@ impl RqProcessorError {
         RqProcessorError {
             func: func.to_string(),
             msg: msg.to_string(),
-            prev_msg
+            prev_msg,
+            kind: RqErrorKind::Internal
         }
     }
     pub fn new_file_err(func: &str, msg: &str, path: &Path, prev_msg: String) -> RqProcessorError {
         RqProcessorError {
             func: func.to_string(),
             msg: format!("{} [path: {:?}]", msg, path),
-            prev_msg
+            prev_msg,
+            kind: RqErrorKind::Internal
         }
     }
+    pub fn with_kind(self, kind: RqErrorKind) -> RqProcessorError {
+        RqProcessorError { kind, ..self }
+    }
+    pub fn kind(&self) -> RqErrorKind {
+        self.kind
+    }
 }
 
 impl std::error::Error for RqProcessorError {}
@@ -71,7 +383,8 @@ impl From<io::Error> for RqProcessorError {
         RqProcessorError {
             func: "RQProcessorError".to_string(),
             msg: String::new(),
-            prev_msg: error.to_string()
+            prev_msg: error.to_string(),
+            kind: RqErrorKind::Internal
         }
     }
 }
@@ -81,7 +394,8 @@ impl From<String> for RqProcessorError {
         RqProcessorError {
             func: "RQProcessorError".to_string(),
             msg: error,
-            prev_msg: String::new()
+            prev_msg: String::new(),
+            kind: RqErrorKind::Internal
         }
     }
 }
@@ -91,7 +405,8 @@ impl From<&str> for RqProcessorError {
         RqProcessorError {
             func: "RQProcessorError".to_string(),
             msg: error.to_string(),
-            prev_msg: String::new()
+            prev_msg: String::new(),
+            kind: RqErrorKind::Internal
         }
     }
 }
@@ -101,7 +416,8 @@ impl From<serde_json::Error> for RqProcessorError {
         RqProcessorError {
             func: "RQProcessorError".to_string(),
             msg: String::new(),
-            prev_msg: error.to_string()
+            prev_msg: error.to_string(),
+            kind: RqErrorKind::Internal
         }
     }
 }
@@ -113,187 +429,1348 @@ impl RaptorQProcessor {
         RaptorQProcessor {
             symbol_size,
             redundancy_factor,
+            control: None,
+            progress: None,
+            rq_ids_mode: RqIdsMode::Uuid { signer: None },
+            rq_ids_compression: RqIdsCompression::None,
+            deterministic_rq_ids: false,
+            rq_ids_file_name: None,
+            work_dir: None,
         }
     }
 
+    /// Reports progress to `control` and stops between symbols once it is cancelled
+    pub fn with_control(mut self, control: Arc<JobControl>) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    pub fn with_rq_ids_mode(mut self, rq_ids_mode: RqIdsMode) -> Self {
+        self.rq_ids_mode = rq_ids_mode;
+        self
+    }
+
+    /// Compression of the JSON ids files, Pastel ids files are compressed anyway
+    pub fn with_rq_ids_compression(mut self, rq_ids_compression: RqIdsCompression) -> Self {
+        self.rq_ids_compression = rq_ids_compression;
+        self
+    }
+
+    /// Names the JSON ids files with `RqIdsFile::deterministic_id` instead of a random uuid,
+    /// so running `create_metadata` again with the same inputs writes the same files
+    pub fn with_deterministic_rq_ids(mut self, deterministic_rq_ids: bool) -> Self {
+        self.deterministic_rq_ids = deterministic_rq_ids;
+        self
+    }
+
+    /// Records `file_name` as the name of the original file in the RQ IDs files, or no name at all,
+    /// instead of the name of the input file - for inputs stored under a temporary name
+    pub fn with_rq_ids_file_name(mut self, file_name: Option<String>) -> Self {
+        self.rq_ids_file_name = Some(file_name);
+        self
+    }
+
+    /// Keeps temporary files, like the file restored by `repair_symbols`, under `work_dir`
+    pub fn with_work_dir(mut self, work_dir: &str) -> Self {
+        self.work_dir = Some(PathBuf::from(work_dir));
+        self
+    }
+
+    /// Calls `f` on the worker thread every time the request makes progress
+    pub fn with_progress<F>(mut self, f: F) -> Self
+        where F: Fn(ProgressEvent) + Send + Sync + 'static {
+        self.progress = Some(ProgressCallback(Arc::new(f)));
+        self
+    }
+
     pub fn create_metadata(&self, path: &String, files_number: u32,
-                           block_hash: &String, pastel_id: &String )
+                           block_hash: &String, pastel_id: &String, options: &OutputOptions )
         -> Result<(EncoderMetaData, String), RqProcessorError> {
 
         let input = Path::new(&path);
-        let (enc, repair_symbols) = self.get_encoder(input)?;
-
-        let names : Vec<String> =
-            enc.get_encoded_packets(repair_symbols)
-            .iter()
-            .map(|packet|
-                {
-                    RaptorQProcessor::symbols_id(&packet.serialize())
-                }
-            ).collect();
+        let file_hash = RaptorQProcessor::file_hash("create_metadata", input)?;
+        let object_id = RaptorQProcessor::object_id(&options.object_id, &file_hash)?;
 
-        let names_len = names.len() as u32;
+        let (mut meta, names) = self.encode_symbols("create_metadata", input, &file_hash, None, options.overwrite, None)?;
 
-        let mut rq_ids_file = RqIdsFile {
-            id: "".to_string(),
-            block_hash: block_hash.to_string(),
-            pastel_id: pastel_id.to_string(),
-            symbol_identifiers: names
-        };
+        let (output_path_str, output_path) =
+            RaptorQProcessor::output_location(input, "meta", &object_id, &options.output_path)?;
+
+        let (rq_ids, rq_ids_counters) = self.write_rq_ids_files(&output_path, files_number, block_hash, pastel_id,
+                                                                input, &meta, &names, options.overwrite)?;
+        meta.rq_ids = rq_ids;
+        meta.rq_ids_counters = rq_ids_counters;
 
+        Ok((meta, output_path_str))
+    }
+
+    pub fn encode(&self, path: &String, options: &OutputOptions) -> Result<(EncoderMetaData, String), RqProcessorError> {
+
+        let input = Path::new(&path);
+        let file_hash = RaptorQProcessor::file_hash("encode", input)?;
+        let object_id = RaptorQProcessor::object_id(&options.object_id, &file_hash)?;
 
         let (output_path_str, output_path) =
-            RaptorQProcessor::output_location(input, "meta")?;
+            RaptorQProcessor::symbols_location(input, &object_id, options)?;
 
-        for _n in 0..files_number {
-            let guid = Uuid::new_v4();
-            let output_file_path = output_path.join(guid.to_string());
+        let output = RaptorQProcessor::symbols_output(&output_path, options);
+        let (meta, _names) = self.encode_symbols("encode", input, &file_hash, Some(output), options.overwrite, None)?;
 
-            rq_ids_file.id = guid.to_string();
-            let j = serde_json::to_string(&rq_ids_file)?;
+        Ok((meta, output_path_str))
+    }
 
-            RaptorQProcessor::create_and_write("create_metadata", &output_file_path,
-                             |output_file| {
-                                 write!(&output_file, "{}", j)
-                             })?;
+    /// Runs the encoding once and writes both the symbol files and the RQ IDs files.
+    /// Returns the symbols location and the RQ IDs files location.
+    pub fn encode_with_metadata(&self, path: &String, files_number: u32,
+                                block_hash: &String, pastel_id: &String, options: &OutputOptions)
+        -> Result<(EncoderMetaData, String, String), RqProcessorError> {
 
+        let input = Path::new(&path);
+        let file_hash = RaptorQProcessor::file_hash("encode_with_metadata", input)?;
+        let object_id = RaptorQProcessor::object_id(&options.object_id, &file_hash)?;
+
+        let (symbols_path_str, symbols_path) =
+            RaptorQProcessor::symbols_location(input, &object_id, options)?;
+        let (meta_path_str, meta_path) =
+            RaptorQProcessor::output_location(input, "meta", &object_id, &options.output_path)?;
+
+        let output = RaptorQProcessor::symbols_output(&symbols_path, options);
+        let (mut meta, names) = self.encode_symbols("encode_with_metadata", input, &file_hash,
+                                                    Some(output), options.overwrite, None)?;
+
+        match self.write_rq_ids_files(&meta_path, files_number, block_hash, pastel_id,
+                                      input, &meta, &names, options.overwrite) {
+            Ok((rq_ids, rq_ids_counters)) => {
+                meta.rq_ids = rq_ids;
+                meta.rq_ids_counters = rq_ids_counters;
+            },
+            Err(err) => {
+                RaptorQProcessor::remove_symbols(output, &names);
+                return Err(err);
+            }
         }
 
+        Ok((meta, symbols_path_str, meta_path_str))
+    }
+
+    /// Encodes the file at `path` without writing anything.
+    /// Every symbol is passed to `f` with its id and payload id as soon as it is generated.
+    pub fn encode_each_symbol<F>(&self, path: &String, mut f: F) -> Result<EncoderMetaData, RqProcessorError>
+        where F: FnMut(&str, PayloadId, Vec<u8>) -> Result<(), RqProcessorError> {
+
+        let input = Path::new(&path);
+        let file_hash = RaptorQProcessor::file_hash("encode_each_symbol", input)?;
+
+        let (meta, _names) = self.encode_symbols("encode_each_symbol", input, &file_hash, None, false, Some(&mut f))?;
+
+        Ok(meta)
+    }
+
+    /// Total size of the serialized symbols `encode` produces for the file at `path`
+    pub fn encoded_size(&self, path: &String) -> Result<u64, RqProcessorError> {
+
+        let (enc, repair_symbols) = self.get_encoder(Path::new(path))?;
+        let symbols_count = enc.source_symbols() as u64 + repair_symbols as u64 * enc.blocks_count() as u64;
+        Ok(symbols_count * (4 + self.symbol_size as u64))
+    }
+
+    /// Reads the symbol files written by `encode` at `path`.
+    /// Every valid symbol is passed to `f` with its id and payload id, other files are skipped.
+    /// Returns the manifest found next to the symbols and the names of the skipped files.
+    pub fn read_each_symbol<F>(&self, path: &String, mut f: F)
+        -> Result<(Option<SymbolsManifest>, Vec<String>), RqProcessorError>
+        where F: FnMut(&str, PayloadId, Vec<u8>) -> Result<(), RqProcessorError> {
+
+        let symbols_path = Path::new(&path);
+        let manifest_path = manifest_path(symbols_path);
+        let manifest = if manifest_path.is_file() {
+            Some(RaptorQProcessor::load_manifest("read_each_symbol", &manifest_path)?)
+        } else {
+            None
+        };
+
+        let symbol_files = match fs::read_dir(symbols_path) {
+            Ok(paths) => paths,
+            Err(err) => {
+                return Err(RqProcessorError::new("read_each_symbol",
+                                                 format!("Cannot get list of input files from {}", &path).as_str(),
+                                                 err.to_string()));
+            }
+        };
+
+        let mut rejected = Vec::new();
+        for symbol_file in symbol_files {
+
+            let file_path = match symbol_file {
+                Ok(path) => path.path(),
+                Err(err) => {
+                    return Err(RqProcessorError::new("read_each_symbol",
+                                                     "Cannot get file path",
+                                                     err.to_string()));
+                }
+            };
+            self.check_cancelled("read_each_symbol")?;
+
+            let mut data = Vec::new();
+            if let Err(err) = RaptorQProcessor::open_and_read("read_each_symbol", &file_path, &mut data) {
+                RaptorQProcessor::reject_symbol(&mut rejected, &file_path, &err.to_string());
+                continue;
+            }
+
+            // Symbol files are named after the hash of their content
+            let id = RaptorQProcessor::symbols_id(&data);
+            if data.len() <= 4 || file_path.file_name() != Some(id.as_ref()) {
+                RaptorQProcessor::reject_symbol(&mut rejected, &file_path,
+                                                "content doesn't match the symbol id");
+                continue;
+            }
+
+            let mut header = [0u8; 4];
+            header.copy_from_slice(&data[..4]);
+            f(&id, PayloadId::deserialize(&header), data)?;
+        }
+
+        Ok((manifest, rejected))
+    }
+
+    // Encodes the input file, returns the ids of all symbols.
+    // Symbol files and the manifest, or the container, are written only when `output` is set,
+    // and removed again when encoding fails or is cancelled.
+    // `sink` gets every symbol after it has been written.
+    fn encode_symbols(&self, func: &str, input: &Path, file_hash: &str,
+                      output: Option<SymbolsOutput>, overwrite: bool, sink: Option<SymbolSink>)
+        -> Result<(EncoderMetaData, Vec<String>), RqProcessorError> {
+
+        // Without overwrite an existing container is not ours to remove
+        let keep_container = !overwrite &&
+            matches!(output, Some(SymbolsOutput::Container(container_path)) if container_path.exists());
+
+        let mut names : Vec<String> = Vec::new();
+        match self.encode_symbols_into(func, input, file_hash, output, overwrite, sink, &mut names) {
+            Ok(meta) => Ok((meta, names)),
+            Err(err) => {
+                match output {
+                    Some(SymbolsOutput::Files(output_path)) => {
+                        RaptorQProcessor::remove_written(output_path, &names);
+                    },
+                    Some(output) if !keep_container => {
+                        RaptorQProcessor::remove_symbols(output, &names);
+                    },
+                    _ => {}
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn encode_symbols_into(&self, func: &str, input: &Path, file_hash: &str,
+                           output: Option<SymbolsOutput>, overwrite: bool, mut sink: Option<SymbolSink>,
+                           names: &mut Vec<String>)
+        -> Result<EncoderMetaData, RqProcessorError> {
+
+        let (mut enc, repair_symbols) = self.get_encoder(input)?;
+        let symbols_total = enc.source_symbols() as u64 + repair_symbols as u64 * enc.blocks_count() as u64;
+        self.start_progress(symbols_total);
+
+        let output_path = match output {
+            Some(SymbolsOutput::Files(output_path)) => Some(output_path),
+            _ => None
+        };
+        let mut container = match output {
+            Some(SymbolsOutput::Container(container_path)) => {
+                let file = RaptorQProcessor::create_file(func, container_path, overwrite)?;
+                match ContainerWriter::new(io::BufWriter::new(file), &enc.get_config(), file_hash, symbols_total as u32) {
+                    Ok(container) => Some((container, container_path)),
+                    Err(err) => {
+                        return Err(RqProcessorError::new_file_err(func,
+                                                                  "Cannot write into the file",
+                                                                  container_path.as_path(),
+                                                                  err.to_string()));
+                    }
+                }
+            },
+            _ => None
+        };
+
+        let mut manifest = SymbolsManifest::new(&enc.get_config(), file_hash);
+        let mut source_symbols = 0u32;
+        while let Some(block) = RaptorQProcessor::next_block(&mut enc, input, func)? {
+            self.report(ProgressEvent::BytesRead {
+                read: enc.bytes_read(),
+                total: enc.get_config().transfer_length() });
+            source_symbols += block.source_symbols();
+            block.for_each_packet(repair_symbols, |symbol| -> Result<(), RqProcessorError> {
+                self.check_cancelled(func)?;
+                let pkt = symbol.serialize();
+                let name = RaptorQProcessor::symbols_id(&pkt);
+
+                if let Some(output_path) = output_path {
+                    let output_file_path = output_path.join(&name);
+                    manifest.add_symbol(&name, &symbol, block.source_symbols());
+
+                    RaptorQProcessor::create_and_write(func, &output_file_path, overwrite,
+                                                       |output_file| {
+                                                           (&output_file).write_all(&pkt)
+                                                       })?;
+                }
+                if let Some((container, container_path)) = container.as_mut() {
+                    if let Err(err) = container.add_symbol(&name, &pkt) {
+                        return Err(RqProcessorError::new_file_err(func,
+                                                                  "Cannot write into the file",
+                                                                  container_path.as_path(),
+                                                                  err.to_string()));
+                    }
+                }
+                if let Some(sink) = sink.as_mut() {
+                    sink(&name, symbol.payload_id().clone(), pkt)?;
+                }
+                names.push(name);
+                self.add_progress(1);
+                self.report(ProgressEvent::SymbolsWritten {
+                    written: names.len() as u64,
+                    total: symbols_total });
+                Ok(())
+            })?;
+        }
+
+        if let Some(output_path) = output_path {
+            let j = serde_json::to_string(&manifest)?;
+            RaptorQProcessor::create_and_write(func, &manifest_path(output_path), overwrite,
+                                               |output_file| {
+                                                   write!(&output_file, "{}", j)
+                                               })?;
+        }
+        if let Some((container, container_path)) = container {
+            if let Err(err) = container.finish() {
+                return Err(RqProcessorError::new_file_err(func,
+                                                          "Cannot write into the file",
+                                                          container_path.as_path(),
+                                                          err.to_string()));
+            }
+        }
+
+        let symbols_count = names.len() as u32;
         Ok(
-            (EncoderMetaData {
+            EncoderMetaData {
                 encoder_parameters: enc.get_config().serialize().to_vec(),
-                source_symbols: names_len - repair_symbols,
-                repair_symbols},
-            output_path_str)
+                source_symbols,
+                repair_symbols: symbols_count - source_symbols,
+                file_hash: file_hash.to_string(),
+                rq_ids: Vec::new(),
+                rq_ids_counters: None}
         )
     }
 
-    pub fn encode(&self, path: &String) -> Result<(EncoderMetaData, String), RqProcessorError> {
+    fn write_rq_ids_files(&self, output_path: &PathBuf, files_number: u32,
+                          block_hash: &String, pastel_id: &String, input: &Path, meta: &EncoderMetaData,
+                          names: &[String], overwrite: bool)
+        -> Result<(Vec<String>, Option<(u32, u32)>), RqProcessorError> {
+
+        let config = RaptorQProcessor::parse_encoder_parameters("create_metadata", &meta.encoder_parameters)?;
+        let mut rq_ids_file = RqIdsFile::new(&config, &meta.file_hash, block_hash, pastel_id);
+        rq_ids_file.file_name = match &self.rq_ids_file_name {
+            Some(file_name) => file_name.clone(),
+            None => input.file_name().map(|name| name.to_string_lossy().to_string())
+        };
+        rq_ids_file.symbol_identifiers = names.to_vec();
+
+        // Pastel ids files only differ by their counter, the signed content is the same for all of them
+        let signed = match &self.rq_ids_mode {
+            RqIdsMode::Pastel { signer, initial_counter } => {
+                if files_number > 0 && initial_counter.checked_add(files_number - 1).is_none() {
+                    return Err(RqProcessorError::new("create_metadata",
+                                                     "Ids counter overflow",
+                                                     format!("initial counter {}, {} files", initial_counter, files_number)));
+                }
+                let encoded = base64::encode(rq_ids_file.canonical_content()?);
+                let signature = signer.sign(encoded.as_bytes())?;
+                Some((format!("{}.{}", encoded, base64::encode(&signature)), *initial_counter))
+            },
+            RqIdsMode::Uuid { signer } => {
+                if let Some(signer) = signer {
+                    let signature = signer.sign(rq_ids_file.canonical_content()?.as_bytes())?;
+                    rq_ids_file.signature = base64::encode(&signature);
+                }
+                None
+            }
+        };
+
+        let mut rq_ids = Vec::new();
+        let mut written = Vec::new();
+        for n in 0..files_number {
+            let res = self.check_cancelled("create_metadata").and_then(|_| {
+                match &signed {
+                    Some((signed, initial_counter)) => {
+                        let content = zstd::encode_all(format!("{}.{}", signed, initial_counter + n).as_bytes(),
+                                                       PASTEL_IDS_COMPRESSION_LEVEL)?;
+                        Ok((RaptorQProcessor::symbols_id(&content), content))
+                    },
+                    None => {
+                        rq_ids_file.id = if self.deterministic_rq_ids {
+                            rq_ids_file.deterministic_id(n)?
+                        } else {
+                            Uuid::new_v4().to_string()
+                        };
+                        let content = self.rq_ids_compression.compress(&serde_json::to_vec(&rq_ids_file)?)?;
+                        Ok((rq_ids_file.id.clone(), content))
+                    }
+                }
+            }).and_then(|(name, content)| {
+                // Files named after their content are left as they are when an earlier run wrote them
+                let file_path = output_path.join(&name);
+                if fs::read(&file_path).map_or(false, |existing| existing == content) {
+                    return Ok((name, false));
+                }
+                RaptorQProcessor::create_and_write("create_metadata", &file_path, overwrite,
+                                                   |output_file| {
+                                                       (&output_file).write_all(&content)
+                                                   })?;
+                Ok((name, true))
+            });
+            match res {
+                Ok((name, created)) => {
+                    if created {
+                        written.push(name.clone());
+                    }
+                    rq_ids.push(name);
+                },
+                Err(err) => {
+                    RaptorQProcessor::remove_written(output_path, &written);
+                    return Err(err);
+                }
+            }
+        }
+
+        let counters = match signed {
+            Some((_, initial_counter)) if files_number > 0 => Some((initial_counter, initial_counter + files_number - 1)),
+            _ => None
+        };
+        Ok((rq_ids, counters))
+    }
+
+    // Removes the symbols written by a failed or cancelled request
+    fn remove_symbols(output: SymbolsOutput, names: &[String]) {
+        match output {
+            SymbolsOutput::Files(output_path) => {
+                let _ = fs::remove_file(manifest_path(output_path));
+                RaptorQProcessor::remove_written(output_path, names);
+            },
+            SymbolsOutput::Container(container_path) => {
+                let _ = fs::remove_file(container_path);
+            }
+        }
+    }
+
+    // Removes files written by a failed or cancelled request, and their directory once it is empty
+    fn remove_written(output_path: &PathBuf, names: &[String]) {
+        for name in names {
+            let _ = fs::remove_file(output_path.join(name));
+        }
+        let _ = fs::remove_dir(output_path);
+    }
+
+    fn check_cancelled(&self, func: &str) -> Result<(), RqProcessorError> {
+        match &self.control {
+            Some(control) if control.is_cancelled() => {
+                Err(RqProcessorError::new(func, "Cancelled", "".to_string()))
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn start_progress(&self, total: u64) {
+        if let Some(control) = &self.control {
+            control.processed.store(0, Ordering::SeqCst);
+            control.total.store(total, Ordering::SeqCst);
+        }
+    }
+
+    fn add_progress(&self, processed: u64) {
+        if let Some(control) = &self.control {
+            control.processed.fetch_add(processed, Ordering::SeqCst);
+        }
+    }
+
+    fn report(&self, event: ProgressEvent) {
+        if let Some(ProgressCallback(f)) = &self.progress {
+            f(event);
+        }
+    }
+
+    /// Restores the original file from the symbols at `path`.
+    /// Returns the restored file location and the names of the files that were skipped
+    /// because they are not valid symbols of the object.
+    /// The encoder parameters and the expected hash default to the ones in the manifest or
+    /// the RQ IDs file at `metadata_path`, or in the manifest next to the symbols.
+    /// The restored file is only written when its SHA3-256 matches the expected hash,
+    /// the one from the metadata is checked even with explicit encoder parameters.
+    pub fn decode(self, encoder_parameters: &Vec<u8>, path: &String, metadata_path: &String,
+                  expected_hash: &String, options: &OutputOptions)
+        -> Result<(String, Vec<String>), RqProcessorError> {
+
+        if path.is_empty() {
+            return Err(RqProcessorError::new("decode",
+                                             "Input symbol's path is empty",
+                                             "".to_string()));
+        }
+        if Path::new(path).is_file() {
+            if !File::open(path).map(is_container).unwrap_or(false) {
+                return Err(RqProcessorError::new_file_err("decode",
+                                                          "Neither a symbols directory nor a symbols container",
+                                                          Path::new(path),
+                                                          "".to_string()));
+            }
+            return self.decode_container(encoder_parameters, path, metadata_path, expected_hash, options);
+        }
+
+        let (config, file_hash) = RaptorQProcessor::decoder_config(encoder_parameters, path, metadata_path)?;
+        let expected_hash = if expected_hash.is_empty() {
+            file_hash
+        } else {
+            expected_hash.to_string()
+        };
+
+        let symbol_files = match fs::read_dir(&path) {
+            Ok(paths) => paths,
+            Err(err) => {
+                return Err(RqProcessorError::new("decode",
+                                                 format!("Cannot get list of input files from {}", &path).as_str(),
+                                                 err.to_string()));
+            }
+        };
+
+        // Group symbol files by source block, so every block can be decoded on its own
+        let mut block_symbols: Vec<Vec<PathBuf>> = vec![Vec::new(); block_layout(&config).len()];
+        let mut rejected = Vec::new();
+        for symbol_file in symbol_files {
+
+            let file_path = match symbol_file {
+                Ok(path) => path.path(),
+                Err(err) => {
+                    return Err(RqProcessorError::new("decode",
+                                                     "Cannot get file path",
+                                                     err.to_string()));
+                }
+            };
+            match RaptorQProcessor::read_payload_id("decode", &file_path) {
+                Ok(Some(id)) if (id.source_block_number() as usize) < block_symbols.len() => {
+                    block_symbols[id.source_block_number() as usize].push(file_path);
+                },
+                Ok(_) => RaptorQProcessor::reject_symbol(&mut rejected, &file_path,
+                                                         "not a symbol of the object"),
+                Err(err) => RaptorQProcessor::reject_symbol(&mut rejected, &file_path,
+                                                            &err.to_string())
+            }
+        }
 
         let input = Path::new(&path);
-        let (enc, repair_symbols) = self.get_encoder(input)?;
+        let rest_file = if options.output_path.is_empty() {
+            RaptorQProcessor::restored_location(input, input.file_name())
+        } else {
+            PathBuf::from(&options.output_path)
+        };
+        let rest_file_str =
+            RaptorQProcessor::path_buf_to_string(
+                &rest_file, "decode", "Invalid path")?;
 
-        let (output_path_str, output_path) =
-            RaptorQProcessor::output_location(input, "symbols")?;
+        let (partial_file, output_file) =
+            RaptorQProcessor::create_partial_output("decode", &rest_file, options.overwrite)?;
+
+        self.start_progress(config.transfer_length());
+        let mut hasher = Sha3_256::new();
+        let mut dec = BlockDecoder::new(output_file, config);
+        let mut progress = DecodeProgress {
+            fed: 0,
+            total: block_symbols.iter().map(|files| files.len() as u64).sum(),
+            restored: 0
+        };
+        for (sbn, symbol_files) in block_symbols.iter().enumerate() {
+            let restored = self.decode_block(&mut dec, sbn as u8, symbol_files,
+                                             &partial_file, &mut hasher, &mut rejected, &mut progress);
+            match restored {
+                Ok(true) => continue,
+                Ok(false) => {
+                    let _ = fs::remove_file(&partial_file);
+                    return Err(RqProcessorError::new("decode",
+                                                     format!("Cannot restore the original file from symbols at {}", path).as_str(),
+                                                     format!("Not enough valid symbols to decode source block {} ({} files skipped)",
+                                                             sbn, rejected.len())));
+                },
+                Err(err) => {
+                    let _ = fs::remove_file(&partial_file);
+                    return Err(err);
+                }
+            }
+        }
+        drop(dec);
+
+        let restored_hash = bs58::encode(&hasher.finalize()).into_string();
+        RaptorQProcessor::move_into_place("decode", &partial_file, &rest_file,
+                                          &restored_hash, &expected_hash)?;
+
+        Ok((rest_file_str, rejected))
+    }
+
+    // Containers have the encoder parameters and the file hash, which are used unless the caller
+    // gives them, or the manifest or the RQ IDs file at `metadata_path` has them - as for directories
+    fn decode_container(self, encoder_parameters: &Vec<u8>, path: &String, metadata_path: &String,
+                        expected_hash: &String, options: &OutputOptions)
+        -> Result<(String, Vec<String>), RqProcessorError> {
 
-        let symbols = enc.get_encoded_packets(repair_symbols);
-        for symbol in &symbols {
-            let pkt = symbol.serialize();
+        let mut reader = RaptorQProcessor::open_container("decode", Path::new(path))?;
+        let (listed_parameters, listed_hash) = if metadata_path.is_empty() {
+            (None, String::new())
+        } else {
+            let (listed_parameters, _ids, file_hash) =
+                RaptorQProcessor::load_symbols_list("decode", &PathBuf::from(metadata_path))?;
+            (listed_parameters, file_hash)
+        };
+        let encoder_parameters = match listed_parameters {
+            _ if !encoder_parameters.is_empty() => encoder_parameters.clone(),
+            Some(listed_parameters) => listed_parameters,
+            None => reader.get_config().serialize().to_vec()
+        };
+        let expected_hash = if !expected_hash.is_empty() {
+            expected_hash.clone()
+        } else if !listed_hash.is_empty() {
+            listed_hash
+        } else {
+            reader.file_hash().to_string()
+        };
+        let output_path = if options.output_path.is_empty() {
+            let container_path = Path::new(path);
+            RaptorQProcessor::restored_location(container_path, container_path.file_stem())
+                .to_string_lossy().to_string()
+        } else {
+            options.output_path.clone()
+        };
+        let options = OutputOptions { output_path, ..options.clone() };
+
+        let ids: Vec<String> = reader.entries().iter().map(|entry| entry.id.clone()).collect();
+        let mut rejected = Vec::new();
+        let packets = ids.iter().enumerate().filter_map(|(index, id)| {
+            let reason = match reader.read_symbol(index) {
+                Ok(data) if RaptorQProcessor::symbols_id(&data) == *id => return Some(data),
+                Ok(_) => "content doesn't match the symbol id".to_string(),
+                Err(err) => err.to_string()
+            };
+            log::warn!("Skipping symbol {} of container {} - {}", id, path, reason);
+            rejected.push(id.clone());
+            None
+        });
+        let (restored, _received, _rejected) = self.decode_packets(&encoder_parameters, packets,
+                                                                   &expected_hash, &options)?;
+        Ok((restored, rejected))
+    }
+
+    /// Lists the symbols packed into the container at `path`
+    pub fn list_container(path: &String) -> Result<ContainerListing, RqProcessorError> {
+
+        let reader = RaptorQProcessor::open_container("list_container", Path::new(path))?;
+        Ok(ContainerListing {
+            encoder_parameters: reader.get_config().serialize().to_vec(),
+            file_hash: reader.file_hash().to_string(),
+            symbols: reader.entries().to_vec()
+        })
+    }
+
+    /// Writes symbols of the container at `path` to files of their own - all of them when `ids` is empty.
+    /// They go to `output_path`, or by default to the directory `encode` would have written them to.
+    /// Returns the symbols location and the ids of the written symbols.
+    pub fn extract_symbols(&self, path: &String, ids: &[String], output_path: &String, overwrite: bool)
+        -> Result<(String, Vec<String>), RqProcessorError> {
+
+        let container_path = Path::new(path);
+        let mut reader = RaptorQProcessor::open_container("extract_symbols", container_path)?;
+        let indexes = if ids.is_empty() {
+            (0..reader.entries().len()).collect()
+        } else {
+            ids.iter().map(|id| {
+                reader.entries().iter().position(|entry| entry.id == *id).ok_or_else(|| {
+                    RqProcessorError::new_file_err("extract_symbols", "No such symbol in the container",
+                                                   container_path, id.clone())
+                })
+            }).collect::<Result<Vec<usize>, RqProcessorError>>()?
+        };
+
+        let output_path = if output_path.is_empty() {
+            container_path.with_extension("")
+        } else {
+            PathBuf::from(output_path)
+        };
+        let output_path_str = RaptorQProcessor::path_buf_to_string(&output_path, "extract_symbols", "Invalid path")?;
+        if let Err(err) = fs::create_dir_all(&output_path) {
+            return Err(RqProcessorError::new_file_err("extract_symbols",
+                                                      "Cannot create output location",
+                                                      output_path.as_path(),
+                                                      err.to_string()));
+        }
+
+        let mut written = Vec::new();
+        for index in indexes {
+            let id = reader.entries()[index].id.clone();
+            let res = self.check_cancelled("extract_symbols").and_then(|_| {
+                let data = match reader.read_symbol(index) {
+                    Ok(data) if RaptorQProcessor::symbols_id(&data) == id => data,
+                    Ok(_) => {
+                        return Err(RqProcessorError::new_file_err("extract_symbols",
+                                                                  "Symbol content doesn't match its id",
+                                                                  container_path, id.clone()));
+                    },
+                    Err(err) => {
+                        return Err(RqProcessorError::new_file_err("extract_symbols",
+                                                                  "Cannot read symbol",
+                                                                  container_path, err.to_string()));
+                    }
+                };
+                RaptorQProcessor::create_and_write("extract_symbols", &output_path.join(&id), overwrite,
+                                                   |output_file| {
+                                                       (&output_file).write_all(&data)
+                                                   })
+            });
+            if let Err(err) = res {
+                RaptorQProcessor::remove_written(&output_path, &written);
+                return Err(err);
+            }
+            written.push(id);
+        }
+        Ok((output_path_str, written))
+    }
+
+    fn open_container(func: &str, path: &Path) -> Result<ContainerReader<File>, RqProcessorError> {
+
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => {
+                return Err(RqProcessorError::new_file_err(func,
+                                                          "Cannot open file",
+                                                          path,
+                                                          err.to_string()));
+            }
+        };
+        match ContainerReader::new(file) {
+            Ok(reader) => Ok(reader),
+            Err(err) => {
+                Err(RqProcessorError::new_file_err(func,
+                                                   "Invalid symbols container",
+                                                   path,
+                                                   err.to_string()))
+            }
+        }
+    }
+
+    /// Restores the original file at `options.output_path` from serialized packets,
+    /// taking them from `packets` as they arrive and stopping as soon as the file is restored.
+    /// Returns the restored file location, the number of packets taken and the number of rejected ones.
+    pub fn decode_packets<I>(self, encoder_parameters: &Vec<u8>, packets: I, expected_hash: &String,
+                             options: &OutputOptions)
+        -> Result<(String, u32, u32), RqProcessorError>
+        where I: Iterator<Item = Vec<u8>> {
+
+        let mut dec = self.packets_decoder(encoder_parameters, expected_hash, options)?;
+        dec.add_packets(packets)?;
+        dec.finish()
+    }
+
+    /// Starts restoring the original file at `options.output_path` from serialized packets,
+    /// for callers that get them in batches - see `decode_packets`.
+    pub fn packets_decoder(self, encoder_parameters: &Vec<u8>, expected_hash: &String, options: &OutputOptions)
+        -> Result<PacketsDecoder, RqProcessorError> {
+
+        let config = RaptorQProcessor::parse_encoder_parameters("decode_packets", encoder_parameters)?;
+        if options.output_path.is_empty() {
+            return Err(RqProcessorError::new("decode_packets",
+                                             "Output path is empty",
+                                             "".to_string()));
+        }
+        let rest_file = PathBuf::from(&options.output_path);
+        let rest_file_str =
+            RaptorQProcessor::path_buf_to_string(
+                &rest_file, "decode_packets", "Invalid path")?;
+
+        let (partial_file, output_file) =
+            RaptorQProcessor::create_partial_output("decode_packets", &rest_file, options.overwrite)?;
+
+        self.start_progress(config.transfer_length());
+        Ok(PacketsDecoder {
+            processor: self,
+            config,
+            dec: Some(StreamDecoder::new(output_file, config)),
+            partial_file,
+            rest_file,
+            rest_file_str,
+            expected_hash: expected_hash.to_string(),
+            received: 0,
+            rejected: 0,
+            restored: 0,
+            finished: false
+        })
+    }
+
+    /// Creates the temporary file the blocks are restored into, next to `rest_file`.
+    /// It only replaces the output once its hash is checked by `move_into_place`.
+    pub fn create_partial_output(func: &str, rest_file: &PathBuf, overwrite: bool)
+        -> Result<(PathBuf, File), RqProcessorError> {
+
+        if !overwrite && rest_file.exists() {
+            return Err(RqProcessorError::new_file_err(func,
+                                                      "Output file already exists",
+                                                      rest_file.as_path(),
+                                                      "".to_string()));
+        }
+        if let Some(parent) = rest_file.parent() {
+            if let Err(err) = fs::create_dir_all(parent) {
+                return Err(RqProcessorError::new_file_err(func,
+                                                          "Cannot create output location",
+                                                          parent,
+                                                          err.to_string()));
+            }
+        }
+
+        let mut partial_file = rest_file.clone().into_os_string();
+        partial_file.push(".partial");
+        let partial_file = PathBuf::from(partial_file);
+        let output_file = RaptorQProcessor::create_file(func, &partial_file, true)?;
+        Ok((partial_file, output_file))
+    }
+
+    /// Moves the restored file into place when its hash matches `expected_hash`, if there is one.
+    /// The partial file is removed when its hash doesn't match, or it cannot be moved.
+    pub fn move_into_place(func: &str, partial_file: &PathBuf, rest_file: &PathBuf,
+                       restored_hash: &str, expected_hash: &str) -> Result<(), RqProcessorError> {
+
+        if !expected_hash.is_empty() && restored_hash != expected_hash {
+            let _ = fs::remove_file(partial_file);
+            return Err(RqProcessorError::new_file_err(func,
+                                                      "Restored file doesn't match the original file",
+                                                      rest_file.as_path(),
+                                                      format!("Expected hash {}, got {}", expected_hash, restored_hash))
+                .with_kind(RqErrorKind::HashMismatch));
+        }
+
+        if let Err(err) = fs::rename(partial_file, rest_file) {
+            let _ = fs::remove_file(partial_file);
+            return Err(RqProcessorError::new_file_err(func,
+                                                      "Cannot move restored file into place",
+                                                      rest_file.as_path(),
+                                                      err.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Regenerates the symbols missing from `path`, or damaged there.
+    /// The expected symbols are listed by the manifest or the RQ IDs file at `metadata_path`,
+    /// or by the manifest next to the symbols when it is empty. Unversioned RQ IDs files don't hold
+    /// the encoder parameters, so they have to be passed with them.
+    /// The object is decoded from the surviving symbols and encoded again with the same parameters.
+    /// Returns the ids of the regenerated symbols.
+    pub fn repair_symbols(&self, encoder_parameters: &Vec<u8>, path: &String, metadata_path: &String)
+        -> Result<Vec<String>, RqProcessorError> {
+
+        let symbols_path = PathBuf::from(path);
+        let (expected, file_hash, config) =
+            RaptorQProcessor::symbols_list("repair_symbols", encoder_parameters, &symbols_path, metadata_path)?;
+        let encoder_parameters = config.serialize().to_vec();
+
+        // Symbol files are named after the hash of their content, anything else is damaged
+        let missing: HashSet<&String> = expected.iter()
+            .filter(|id| match fs::read(symbols_path.join(id.as_str())) {
+                Ok(data) => RaptorQProcessor::symbols_id(&data) != **id,
+                Err(_) => true
+            })
+            .collect();
+        if missing.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let restored_file = self.temporary_file("repair_symbols", REPAIR_DIR)?;
+        let options = OutputOptions {
+            output_path: RaptorQProcessor::path_buf_to_string(&restored_file.0, "repair_symbols", "Invalid path")?,
+            ..Default::default() };
+        self.clone().decode(&encoder_parameters, path, &String::new(), &file_hash, &options)?;
+
+        let regenerated = self.regenerate_symbols(&restored_file.0, &config, &symbols_path, expected.len(), &missing)?;
+
+        if regenerated.len() != missing.len() {
+            return Err(RqProcessorError::new_file_err("repair_symbols",
+                                                      "Symbols of the restored file don't match the symbols list",
+                                                      symbols_path.as_path(),
+                                                      format!("{} of {} missing symbols regenerated",
+                                                              regenerated.len(), missing.len())));
+        }
+        Ok(regenerated)
+    }
+
+    // A new file name in the `sub` directory of the work dir, the file is removed once it is dropped
+    fn temporary_file(&self, func: &str, sub: &str) -> Result<TemporaryFile, RqProcessorError> {
+
+        let dir = self.work_dir.clone().unwrap_or_else(std::env::temp_dir).join(sub);
+        RaptorQProcessor::create_output_dir(func, &dir)?;
+        Ok(TemporaryFile(dir.join(Uuid::new_v4().to_string())))
+    }
+
+    // Encodes the restored file again, writing the symbols in `missing`
+    fn regenerate_symbols(&self, restored_file: &Path, config: &ObjectTransmissionInformation, symbols_path: &PathBuf,
+                          symbols_count: usize, missing: &HashSet<&String>)
+        -> Result<Vec<String>, RqProcessorError> {
+
+        let processor = RaptorQProcessor::new(config.symbol_size(), self.redundancy_factor);
+        let (mut enc, _repair_symbols) = processor.get_encoder(restored_file)?;
+        if enc.get_config().serialize() != config.serialize() {
+            return Err(RqProcessorError::new_file_err("repair_symbols",
+                                                      "Restored file doesn't encode with the original parameters",
+                                                      restored_file,
+                                                      "".to_string()));
+        }
+
+        // Every block gets the same number of repair symbols, so it follows from the symbols count
+        let repair_symbols = symbols_count.saturating_sub(enc.source_symbols() as usize) / enc.blocks_count();
+
+        let mut regenerated = Vec::new();
+        while let Some(block) = RaptorQProcessor::next_block(&mut enc, restored_file, "repair_symbols")? {
+            block.for_each_packet(repair_symbols as u32, |symbol| -> Result<(), RqProcessorError> {
+                self.check_cancelled("repair_symbols")?;
+                let pkt = symbol.serialize();
+                let name = RaptorQProcessor::symbols_id(&pkt);
+                if missing.contains(&name) {
+                    RaptorQProcessor::create_and_write("repair_symbols", &symbols_path.join(&name), true,
+                                                       |output_file| {
+                                                           (&output_file).write_all(&pkt)
+                                                       })?;
+                    regenerated.push(name);
+                }
+                Ok(())
+            })?;
+        }
+        Ok(regenerated)
+    }
+
+    /// Checks that the RQ IDs file at `rq_ids_path` is the one `create_metadata` writes
+    /// for the file at `path` with the current symbol size and redundancy factor.
+    /// The order of the symbol ids is not significant.
+    pub fn verify_metadata(&self, path: &String, rq_ids_path: &String, block_hash: &String, pastel_id: &String)
+        -> Result<MetadataVerification, RqProcessorError> {
+
+        let rq_ids_file = RqIdsFile::load(Path::new(rq_ids_path))?;
+
+        let input = Path::new(&path);
+        let file_hash = RaptorQProcessor::file_hash("verify_metadata", input)?;
+        let (_meta, names) = self.encode_symbols("verify_metadata", input, &file_hash, None, false, None)?;
+
+        let listed: HashSet<&String> = rq_ids_file.symbol_identifiers.iter().collect();
+        let encoded: HashSet<&String> = names.iter().collect();
+        let missing_symbols: Vec<String> = names.iter()
+            .filter(|name| !listed.contains(name))
+            .cloned()
+            .collect();
+        let unexpected_symbols: Vec<String> = rq_ids_file.symbol_identifiers.iter()
+            .filter(|name| !encoded.contains(name))
+            .cloned()
+            .collect();
+
+        Ok(MetadataVerification {
+            symbols_match: missing_symbols.is_empty() && unexpected_symbols.is_empty(),
+            block_hash_matches: rq_ids_file.block_hash == *block_hash,
+            pastel_id_matches: rq_ids_file.pastel_id == *pastel_id,
+            missing_symbols,
+            unexpected_symbols
+        })
+    }
+
+    /// Checks the signature of the RQ IDs file at `rq_ids_path` with the Ed25519 `public_key`.
+    /// Reads both the JSON ids files and the Pastel ids files.
+    pub fn verify_ids_file(rq_ids_path: &String, public_key: &[u8])
+        -> Result<IdsFileVerification, RqProcessorError> {
+
+        let public_key = match PublicKey::from_bytes(public_key) {
+            Ok(public_key) => public_key,
+            Err(err) => {
+                return Err(RqProcessorError::new("verify_ids_file",
+                                                 "Invalid Ed25519 public key",
+                                                 err.to_string()));
+            }
+        };
+
+        let signed = RqIdsFile::load_signed(Path::new(rq_ids_path))?;
+        let signature_valid = match Signature::try_from(&signed.signature[..]) {
+            Ok(signature) => public_key.verify(&signed.signed_content, &signature).is_ok(),
+            Err(_) => false
+        };
+        Ok(IdsFileVerification {
+            signed: !signed.signature.is_empty(),
+            signature_valid,
+            block_hash: signed.rq_ids.block_hash,
+            pastel_id: signed.rq_ids.pastel_id,
+            file_hash: signed.rq_ids.file_hash,
+            symbols_count: signed.rq_ids.symbol_identifiers.len() as u32,
+            counter: signed.counter
+        })
+    }
+
+    /// Compares the symbol files at `path` with the symbols listed by the manifest or
+    /// the RQ IDs file at `metadata_path`, found the same way as by `repair_symbols`.
+    /// Blocks are expected to decode from as many valid symbols as `StreamDecoder` expects them to.
+    /// Subdirectories are skipped, a file that cannot be read fails the check.
+    pub fn check_symbols(&self, encoder_parameters: &Vec<u8>, path: &String, metadata_path: &String)
+        -> Result<SymbolsCheck, RqProcessorError> {
+
+        let symbols_path = PathBuf::from(path);
+        let (listed, _file_hash, config) =
+            RaptorQProcessor::symbols_list("check_symbols", encoder_parameters, &symbols_path, metadata_path)?;
+
+        let symbol_files = match fs::read_dir(&symbols_path) {
+            Ok(paths) => paths,
+            Err(err) => {
+                return Err(RqProcessorError::new("check_symbols",
+                                                 format!("Cannot get list of input files from {}", &path).as_str(),
+                                                 err.to_string()));
+            }
+        };
+
+        let listed_ids: HashSet<&String> = listed.iter().collect();
+        let mut found = HashSet::new();
+        let mut check = SymbolsCheck::default();
+        let dec = BlockDecoder::new(io::Cursor::new(Vec::new()), config);
+        let mut block_symbols = vec![0u32; dec.blocks_count()];
+        for symbol_file in symbol_files {
+
+            let file_path = match symbol_file {
+                Ok(path) => path.path(),
+                Err(err) => {
+                    return Err(RqProcessorError::new("check_symbols",
+                                                     "Cannot get file path",
+                                                     err.to_string()));
+                }
+            };
+            // Subdirectories, like one `extract_symbols` has written to, don't hold symbols of their own
+            if !file_path.is_file() {
+                continue;
+            }
+            let name = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            self.check_cancelled("check_symbols")?;
+
+            // Symbol files are named after the hash of their content
+            let mut data = Vec::new();
+            RaptorQProcessor::open_and_read("check_symbols", &file_path, &mut data)?;
+            if RaptorQProcessor::symbols_id(&data) != name {
+                check.corrupted.push(name.clone());
+                found.insert(name);
+                continue;
+            }
+            if !listed_ids.contains(&name) {
+                check.unlisted.push(name);
+                continue;
+            }
+
+            if let Some(packet) = dec.parse_packet(&data) {
+                block_symbols[packet.payload_id().source_block_number() as usize] += 1;
+            }
+            check.valid.push(name.clone());
+            found.insert(name);
+        }
+
+        check.missing = listed.iter()
+            .filter(|id| !found.contains(*id))
+            .cloned()
+            .collect();
+
+        let symbol_size = config.symbol_size() as u64;
+        check.symbols_needed = block_layout(&config).iter()
+            .zip(block_symbols)
+            .map(|(block, symbols)| block_symbols_needed((block.length / symbol_size) as u32, symbols))
+            .sum();
+
+        Ok(check)
+    }
+
+    // The symbols listed by the manifest or the RQ IDs file at `metadata_path`, or by the manifest
+    // next to the symbols when it is empty, with the file hash and the encoder parameters -
+    // `encoder_parameters` when they are given, the ones from the list otherwise
+    fn symbols_list(func: &str, encoder_parameters: &Vec<u8>, symbols_path: &PathBuf, metadata_path: &String)
+        -> Result<(Vec<String>, String, ObjectTransmissionInformation), RqProcessorError> {
+
+        let metadata_path = if metadata_path.is_empty() {
+            manifest_path(symbols_path)
+        } else {
+            PathBuf::from(metadata_path)
+        };
+        let (listed_parameters, listed, file_hash) = RaptorQProcessor::load_symbols_list(func, &metadata_path)?;
+        let encoder_parameters = match listed_parameters {
+            _ if !encoder_parameters.is_empty() => encoder_parameters.clone(),
+            Some(listed_parameters) => listed_parameters,
+            None => {
+                return Err(RqProcessorError::new_file_err(func,
+                                                          "encoder_parameters are required, the RQ IDs file doesn't have them",
+                                                          metadata_path.as_path(),
+                                                          "".to_string()));
+            }
+        };
+        let config = RaptorQProcessor::parse_encoder_parameters(func, &encoder_parameters)?;
+        Ok((listed, file_hash, config))
+    }
+
+    // Reads the list of symbols from a manifest or an RQ IDs file.
+    // Returns the encoder parameters, when the file has them, the symbol ids and the file hash.
+    fn load_symbols_list(func: &str, metadata_path: &PathBuf)
+        -> Result<(Option<Vec<u8>>, Vec<String>, String), RqProcessorError> {
+
+        let mut data = Vec::new();
+        RaptorQProcessor::open_and_read(func, metadata_path, &mut data)?;
+
+        if let Ok(manifest) = serde_json::from_slice::<SymbolsManifest>(&data) {
+            if manifest.version > MANIFEST_VERSION {
+                return Err(RqProcessorError::new_file_err(func,
+                                                          "Unsupported manifest version",
+                                                          metadata_path.as_path(),
+                                                          format!("version {}", manifest.version)));
+            }
+            let ids = manifest.symbols.into_iter().map(|symbol| symbol.id).collect();
+            return Ok((Some(manifest.encoder_parameters), ids, manifest.file_hash));
+        }
+        match RqIdsFile::parse(&data) {
+            Ok(rq_ids_file) => Ok((rq_ids_file.encoder_parameters, rq_ids_file.symbol_identifiers, rq_ids_file.file_hash)),
+            Err(err) => {
+                Err(RqProcessorError::new_file_err(func,
+                                                   "Neither a manifest nor an RQ IDs file",
+                                                   metadata_path.as_path(),
+                                                   err.to_string()))
+            }
+        }
+    }
 
-            let name = RaptorQProcessor::symbols_id(&pkt);
-            let output_file_path = output_path.join(name);
+    // Explicitly passed encoder parameters, or the ones stored in the manifest or the RQ IDs file
+    // at `metadata_path`, or in the manifest written next to the symbols.
+    // The hash of the original file is returned whenever the metadata has it, empty otherwise.
+    fn decoder_config(encoder_parameters: &Vec<u8>, path: &String, metadata_path: &String)
+        -> Result<(ObjectTransmissionInformation, String), RqProcessorError> {
 
-            RaptorQProcessor::create_and_write("encode", &output_file_path,
-                                               |output_file| {
-                                                   (&output_file).write_all(&pkt)
-                                               })?;
-        }
+        let metadata_file = if metadata_path.is_empty() {
+            manifest_path(Path::new(path))
+        } else {
+            PathBuf::from(metadata_path)
+        };
+        let (listed_parameters, file_hash) = if metadata_path.is_empty() && !metadata_file.is_file() {
+            (None, String::new())
+        } else {
+            let (listed_parameters, _ids, file_hash) = RaptorQProcessor::load_symbols_list("decode", &metadata_file)?;
+            (listed_parameters, file_hash)
+        };
 
-        Ok(
-            (EncoderMetaData {
-            encoder_parameters: enc.get_config().serialize().to_vec(),
-            source_symbols: symbols.len() as u32 - repair_symbols,
-            repair_symbols},
-            output_path_str
-            )
-        )
+        let encoder_parameters = match listed_parameters {
+            _ if !encoder_parameters.is_empty() => encoder_parameters.clone(),
+            Some(listed_parameters) => listed_parameters,
+            None => {
+                return Err(RqProcessorError::new_file_err("decode",
+                                                          "encoder_parameters are empty and the metadata doesn't have them",
+                                                          metadata_file.as_path(),
+                                                          "".to_string()));
+            }
+        };
+        let config = RaptorQProcessor::parse_encoder_parameters("decode", &encoder_parameters)?;
+        Ok((config, file_hash))
     }
 
-    pub fn decode(self, encoder_parameters: &Vec<u8>, path: &String)
-        -> Result<String, RqProcessorError> {
+    pub fn parse_encoder_parameters(func: &str, encoder_parameters: &Vec<u8>)
+        -> Result<ObjectTransmissionInformation, RqProcessorError> {
 
-        if path.is_empty() {
-            return Err(RqProcessorError::new("decode",
-                                             "Input symbol's path is empty",
-                                             "".to_string()));
+        let mut cfg = [0u8; 12];
+        if encoder_parameters.len() != cfg.len() {
+            return Err(RqProcessorError::new(func,
+                                             "Invalid encoder_parameters",
+                                             format!("Expected {} bytes, got {}",
+                                                     cfg.len(), encoder_parameters.len())));
         }
-        if encoder_parameters.len() == 0 {
-            return Err(RqProcessorError::new("decode",
-                                             "encoder_parameters are empty",
-                                             "".to_string()));
+        cfg.copy_from_slice(encoder_parameters);
+
+        let config = ObjectTransmissionInformation::deserialize(&cfg);
+        if let Err(err) = check_config(&config) {
+            return Err(RqProcessorError::new(func, "Invalid encoder_parameters", err));
         }
+        Ok(config)
+    }
 
-        let mut cfg = [0u8; 12];
-        cfg.iter_mut().set_from(encoder_parameters.iter().cloned());
+    fn load_manifest(func: &str, manifest_path: &PathBuf) -> Result<SymbolsManifest, RqProcessorError> {
 
-        let config = ObjectTransmissionInformation::deserialize(&cfg);
-        let mut dec = Decoder::new(config);
+        let mut data = Vec::new();
+        RaptorQProcessor::open_and_read(func, manifest_path, &mut data)?;
 
-        let symbol_files = match fs::read_dir(&path) {
-            Ok(paths) => paths,
+        let manifest: SymbolsManifest = match serde_json::from_slice(&data) {
+            Ok(manifest) => manifest,
             Err(err) => {
-                return Err(RqProcessorError::new("decode",
-                                                 format!("Cannot get list of input files from {}", &path).as_str(),
-                                                 err.to_string()));
+                return Err(RqProcessorError::new_file_err(func,
+                                                          "Invalid manifest",
+                                                          manifest_path.as_path(),
+                                                          err.to_string()));
             }
         };
+        if manifest.version > MANIFEST_VERSION {
+            return Err(RqProcessorError::new_file_err(func,
+                                                      "Unsupported manifest version",
+                                                      manifest_path.as_path(),
+                                                      format!("version {}", manifest.version)));
+        }
+        Ok(manifest)
+    }
 
-        for symbol_file in symbol_files {
+    fn decode_block(&self, dec: &mut BlockDecoder<File>, sbn: u8, symbol_files: &[PathBuf], output_path: &PathBuf,
+                    hasher: &mut Sha3_256, rejected: &mut Vec<String>, progress: &mut DecodeProgress)
+        -> Result<bool, RqProcessorError> {
 
-            let file_path = match symbol_file {
-                Ok(path) => path.path(),
-                Err(err) => {
-                    return Err(RqProcessorError::new("decode",
-                                                     "Cannot get file path",
-                                                     err.to_string()));
-                }
-            };
+        let mut block_dec = dec.source_block_decoder(sbn);
+        for file_path in symbol_files {
+            self.check_cancelled("decode")?;
 
             let mut data = Vec::new();
-            RaptorQProcessor::open_and_read("decode", &file_path, &mut data)?;
-
-            if let Some(result) = dec.decode(EncodingPacket::deserialize(&data)) {
+            if let Err(err) = RaptorQProcessor::open_and_read("decode", file_path, &mut data) {
+                RaptorQProcessor::reject_symbol(rejected, file_path, &err.to_string());
+                continue;
+            }
 
-                let input = Path::new(&path);
-                let rest_file = input.with_file_name("restored_file");
-                let rest_file_str =
-                    RaptorQProcessor::path_buf_to_string(
-                        &rest_file, "decode", "Invalid path")?;
+            // Symbol files are named after the hash of their content
+            let name = file_path.file_name().and_then(|name| name.to_str());
+            if name != Some(RaptorQProcessor::symbols_id(&data).as_str()) {
+                RaptorQProcessor::reject_symbol(rejected, file_path,
+                                                "content doesn't match the symbol id");
+                continue;
+            }
 
-                RaptorQProcessor::create_and_write("decode", &rest_file,
-                                                   |output_file| {
-                                                       (&output_file).write_all(&result)
-                                                   })?;
-                return Ok(rest_file_str);
+            let packet = match dec.parse_packet(&data) {
+                Some(packet) if packet.payload_id().source_block_number() == sbn => packet,
+                _ => {
+                    RaptorQProcessor::reject_symbol(rejected, file_path,
+                                                    "not a symbol of the object");
+                    continue;
+                }
             };
+
+            let decoded = block_dec.decode(iter::once(packet));
+            progress.fed += 1;
+            self.report(ProgressEvent::SymbolsFed { fed: progress.fed, total: progress.total });
+
+            if let Some(result) = decoded {
+                // Blocks are restored in order, so hashing them one after another hashes the whole file
+                match dec.write_block(sbn, &result) {
+                    Ok(len) => {
+                        hasher.update(&result[..len]);
+                        self.add_progress(len as u64);
+                        progress.restored += len as u64;
+                        self.report(ProgressEvent::BytesRestored {
+                            restored: progress.restored,
+                            total: dec.get_config().transfer_length() });
+                    },
+                    Err(err) => {
+                        return Err(RqProcessorError::new_file_err("decode",
+                                                                  "Cannot write into the file",
+                                                                  output_path.as_path(),
+                                                                  err.to_string()));
+                    }
+                }
+                return Ok(true);
+            }
         }
+        Ok(false)
+    }
 
-        Err(RqProcessorError::new("decode",
-                                  format!("Cannot restore the original file from symbols at {}", path).as_str(),
-                                  "".to_string()))
+    fn reject_symbol(rejected: &mut Vec<String>, file_path: &Path, reason: &str) {
+        log::warn!("Skipping symbol file {:?} - {}", file_path, reason);
+        let name = match file_path.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => file_path.to_string_lossy().to_string()
+        };
+        rejected.push(name);
     }
 
-    fn output_location(input: &Path, sub: &str)
+    // Every object gets its own directory - <input parent>/<sub>/<object_id>,
+    // or <output_root>/<sub>/<object_id> when the caller has chosen the output root
+    fn output_location(input: &Path, sub: &str, object_id: &str, output_root: &String)
                        -> Result<(String, PathBuf), RqProcessorError> {
 
-        let output_path = match input.parent() {
-            Some(p) => {
-                if !sub.is_empty() {
-                    p.join(sub)
-                } else {
-                    p.to_path_buf()
-                }
-            },
+        let output_path = RaptorQProcessor::output_dir(input, sub, output_root)?.join(object_id);
+        RaptorQProcessor::create_output_dir("output_location", &output_path)?;
+
+        match RaptorQProcessor::path_buf_to_string(&output_path, "output_location", "Invalid path"){
+            Ok(path_str) => Ok((path_str.to_string(), output_path)),
+            Err(err) => Err(err)
+        }
+
+    }
+
+    // The directory the objects' outputs go to - <input parent>/<sub>, or <output_root>/<sub>
+    fn output_dir(input: &Path, sub: &str, output_root: &String) -> Result<PathBuf, RqProcessorError> {
+
+        let parent = if output_root.is_empty() {
+            input.parent()
+        } else {
+            Some(Path::new(output_root))
+        };
+        match parent {
+            Some(p) => Ok(p.join(sub)),
             None => {
-                return Err(RqProcessorError::new_file_err("output_location",
-                                                          "Cannot get parent of the input location",
-                                                          input,
-                                                          "".to_string()));
+                Err(RqProcessorError::new_file_err("output_location",
+                                                   "Cannot get parent of the input location",
+                                                   input,
+                                                   "".to_string()))
             }
-        };
-        if let Err(err) = fs::create_dir_all(&output_path)
-        {
-            return Err(RqProcessorError::new_file_err("output_location",
+        }
+    }
+
+    fn create_output_dir(func: &str, path: &Path) -> Result<(), RqProcessorError> {
+
+        if let Err(err) = fs::create_dir_all(path) {
+            return Err(RqProcessorError::new_file_err(func,
                                                       "Cannot create output location",
-                                                      output_path.as_path(),
+                                                      path,
                                                       err.to_string()));
         }
+        Ok(())
+    }
+
+    // The symbols directory of the object, or its container <input parent>/symbols/<object_id>.rqc
+    fn symbols_location(input: &Path, object_id: &str, options: &OutputOptions)
+                        -> Result<(String, PathBuf), RqProcessorError> {
+
+        if !options.container {
+            return RaptorQProcessor::output_location(input, "symbols", object_id, &options.output_path);
+        }
+        let symbols_dir = RaptorQProcessor::output_dir(input, "symbols", &options.output_path)?;
+        RaptorQProcessor::create_output_dir("output_location", &symbols_dir)?;
+        let container_path = symbols_dir.join(format!("{}.{}", object_id, CONTAINER_EXTENSION));
+        let container_path_str =
+            RaptorQProcessor::path_buf_to_string(&container_path, "output_location", "Invalid path")?;
+        Ok((container_path_str, container_path))
+    }
 
-        match RaptorQProcessor::path_buf_to_string(&output_path, "output_location", "Invalid path"){
-            Ok(path_str) => Ok((path_str.to_string(), output_path)),
-            Err(err) => Err(err)
+    fn symbols_output<'a>(symbols_path: &'a PathBuf, options: &OutputOptions) -> SymbolsOutput<'a> {
+        if options.container {
+            SymbolsOutput::Container(symbols_path)
+        } else {
+            SymbolsOutput::Files(symbols_path)
         }
+    }
+
+    // Default location of a restored object, next to its symbols and named after it -
+    // <symbols dir>.restored, or <container without extension>.restored
+    fn restored_location(symbols_path: &Path, object_name: Option<&OsStr>) -> PathBuf {
+        match object_name {
+            Some(name) => symbols_path.with_file_name(format!("{}.restored", name.to_string_lossy())),
+            None => symbols_path.with_file_name("restored_file")
+        }
+    }
 
+    // Caller supplied id, or the content hash of the input file when it is empty
+    fn object_id(object_id: &String, file_hash: &str) -> Result<String, RqProcessorError> {
+
+        if object_id.is_empty() {
+            return Ok(file_hash.to_string());
+        }
+
+        let mut components = Path::new(object_id).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => Ok(object_id.to_string()),
+            _ => Err(RqProcessorError::new("object_id",
+                                           format!("Invalid object id {:?}", object_id).as_str(),
+                                           "Object id must be a single path component".to_string()))
+        }
     }
 
     fn path_buf_to_string(path: &PathBuf, func: &str, msg: &str) -> Result<String, RqProcessorError> {
@@ -306,9 +1783,9 @@ impl RaptorQProcessor {
         }
     }
 
-    fn get_encoder(&self, path: &Path) -> Result<(Encoder, u32), RqProcessorError> {
+    fn get_encoder(&self, path: &Path) -> Result<(BlockEncoder<File>, u32), RqProcessorError> {
 
-        let mut file = match File::open(&path){
+        let file = match File::open(&path){
             Ok(file) => file,
             Err(err) => {
                 return Err(RqProcessorError::new_file_err("get_encoder",
@@ -333,14 +1810,19 @@ impl RaptorQProcessor {
             self.symbol_size,
         );
 
-        let mut data= Vec::new();
-        match file.read_to_end(&mut data) {
-            Ok(_) => Ok((Encoder::new(&data, config),
-                         RaptorQProcessor::repair_symbols_num(self.symbol_size,
-                                                              self.redundancy_factor,
-                                                              source_size))),
+        Ok((BlockEncoder::new(file, config),
+            RaptorQProcessor::repair_symbols_num(self.symbol_size,
+                                                 self.redundancy_factor,
+                                                 source_size)))
+    }
+
+    fn next_block(enc: &mut BlockEncoder<File>, path: &Path, func: &str)
+        -> Result<Option<EncodedBlock>, RqProcessorError> {
+
+        match enc.next_block() {
+            Ok(block) => Ok(block),
             Err(err) => {
-                Err(RqProcessorError::new_file_err("get_encoder",
+                Err(RqProcessorError::new_file_err(func,
                                                    "Cannot read input file",
                                                    path,
                                                    err.to_string()))
@@ -348,19 +1830,33 @@ impl RaptorQProcessor {
         }
     }
 
-    fn create_and_write<F>(func: &str, output_file_path: &PathBuf, f: F)
-                           -> Result<(), RqProcessorError>
-        where F: Fn(File) -> std::io::Result<()> {
+    // Existing file is only replaced when `overwrite` is set
+    fn create_file(func: &str, output_file_path: &PathBuf, overwrite: bool)
+                   -> Result<File, RqProcessorError> {
 
-        let output_file = match File::create(&output_file_path){
-            Ok(file) => file,
+        let mut open_options = fs::OpenOptions::new();
+        if overwrite {
+            open_options.write(true).create(true).truncate(true);
+        } else {
+            open_options.write(true).create_new(true);
+        }
+
+        match open_options.open(&output_file_path){
+            Ok(file) => Ok(file),
             Err(err) => {
-                return Err(RqProcessorError::new_file_err(func,
-                                                          "Cannot create file",
-                                                          output_file_path.as_path(),
-                                                          err.to_string()));
+                Err(RqProcessorError::new_file_err(func,
+                                                   "Cannot create file",
+                                                   output_file_path.as_path(),
+                                                   err.to_string()))
             }
-        };
+        }
+    }
+
+    fn create_and_write<F>(func: &str, output_file_path: &PathBuf, overwrite: bool, f: F)
+                           -> Result<(), RqProcessorError>
+        where F: Fn(File) -> std::io::Result<()> {
+
+        let output_file = RaptorQProcessor::create_file(func, output_file_path, overwrite)?;
 
         if let Err(err) = f(output_file) {
             return Err(RqProcessorError::new_file_err(func,
@@ -392,6 +1888,31 @@ impl RaptorQProcessor {
         Ok(())
     }
 
+    fn read_payload_id(func: &str, file_path: &PathBuf) -> Result<Option<PayloadId>, RqProcessorError> {
+
+        let mut file = match File::open(&file_path) {
+            Ok(file) => file,
+            Err(err) => {
+                return Err(RqProcessorError::new_file_err(func,
+                                                          "Cannot open file",
+                                                          file_path.as_path(),
+                                                          err.to_string()));
+            }
+        };
+
+        let mut header = [0u8; 4];
+        match file.read_exact(&mut header) {
+            Ok(_) => Ok(Some(PayloadId::deserialize(&header))),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => {
+                Err(RqProcessorError::new_file_err(func,
+                                                   "Cannot read input file",
+                                                   file_path.as_path(),
+                                                   err.to_string()))
+            }
+        }
+    }
+
     fn repair_symbols_num(symbol_size: u16, redundancy_factor: u8, data_len: u64) -> u32 {
         if data_len <= symbol_size as u64 {
             redundancy_factor as u32
@@ -402,6 +1923,37 @@ impl RaptorQProcessor {
         }
     }
 
+    /// Base58 SHA3-256 of the file content, read in chunks
+    pub fn file_hash(func: &str, path: &Path) -> Result<String, RqProcessorError> {
+
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                return Err(RqProcessorError::new_file_err(func,
+                                                          "Cannot open file",
+                                                          path,
+                                                          err.to_string()));
+            }
+        };
+
+        let mut hasher = Sha3_256::new();
+        let mut buf = vec![0u8; 1024 * 1024];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => hasher.update(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    return Err(RqProcessorError::new_file_err(func,
+                                                              "Cannot read input file",
+                                                              path,
+                                                              err.to_string()));
+                }
+            }
+        }
+        Ok(bs58::encode(&hasher.finalize()).into_string())
+    }
+
     fn symbols_id(symbol: &Vec<u8>) -> String {
         let mut hasher = Sha3_256::new();
         hasher.update(symbol);
@@ -429,7 +1981,8 @@ mod tests {
             12);
 
         let encode_time = Instant::now();
-        match processor.create_metadata(&path, 50, &String::from("12345"), &String::from("67890")) {
+        let options = OutputOptions { overwrite: true, ..Default::default() };
+        match processor.create_metadata(&path, 50, &String::from("12345"), &String::from("67890"), &options) {
             Ok((meta, path)) => {
                 println!("source symbols = {}; repair symbols = {}", meta.source_symbols, meta.repair_symbols);
 
@@ -457,7 +2010,8 @@ mod tests {
             12);
 
         let encode_time = Instant::now();
-        match processor.encode(&path) {
+        let options = OutputOptions { overwrite: true, ..Default::default() };
+        match processor.encode(&path, &options) {
             Ok((meta, path)) => {
                 println!("source symbols = {}; repair symbols = {}", meta.source_symbols, meta.repair_symbols);
 
@@ -487,7 +2041,8 @@ mod tests {
 
         let encode_time = Instant::now();
 
-        match processor.decode(encoder_parameters, &path) {
+        let options = OutputOptions { overwrite: true, ..Default::default() };
+        match processor.decode(encoder_parameters, &path, &String::new(), &String::new(), &options) {
             Ok(_outpat) => {
                 // assert_eq!(symbols_count, (source_symbols + meta.repair_symbols) as usize);
             },
@@ -507,10 +2062,632 @@ mod tests {
 
     #[test]
     fn rq_test_encode_decode() {
-        let (meta, _path) = test_encode(String::from("test/10_000_000"), 10_000_000).unwrap();
-        test_decode(&meta.encoder_parameters, &"test/symbols".to_string());
+        let (meta, path) = test_encode(String::from("test/10_000_000"), 10_000_000).unwrap();
+        test_decode(&meta.encoder_parameters, &path);
+        // Encoder parameters from the manifest
+        test_decode(&Vec::new(), &path);
 
         // test_encode(String::from("test/10_000"), 10_000);
         // test_encode(String::from("test/10_000_001"), 10_000_001);
     }
+
+    #[test]
+    fn rq_test_default_restore_location() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12);
+
+        // Objects encoded next to each other are restored next to each other, without overwrite
+        for object_id in &["first", "second"] {
+            let options = OutputOptions {
+                object_id: object_id.to_string(),
+                overwrite: true,
+                ..Default::default() };
+            let (meta, path) = processor.encode(&String::from("test/10_000"), &options).unwrap();
+
+            let restored_path = format!("test/symbols/{}.restored", object_id);
+            let _ = fs::remove_file(&restored_path);
+            let (restored, _rejected) = processor.clone()
+                .decode(&meta.encoder_parameters, &path, &String::new(), &String::new(), &OutputOptions::default())
+                .unwrap();
+            assert_eq!(restored, restored_path);
+            assert_eq!(fs::read(&restored).unwrap(), fs::read("test/10_000").unwrap());
+        }
+    }
+
+    #[test]
+    fn rq_test_decode_corrupted_symbols() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12);
+
+        let options = OutputOptions {
+            object_id: "corrupted".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let (meta, path) = processor.encode(&String::from("test/10_000"), &options).unwrap();
+
+        // 1 source and 12 repair symbols - damage a few, the rest is enough to decode
+        let mut names: Vec<PathBuf> = fs::read_dir(&path).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.file_name().unwrap() != "unrelated")
+            .collect();
+        names.sort();
+
+        let mut data = fs::read(&names[0]).unwrap();
+        data[100] ^= 1;
+        fs::write(&names[0], &data).unwrap();
+        fs::write(&names[1], &data[..3]).unwrap();
+        fs::write(Path::new(&path).join("unrelated"), b"not a symbol").unwrap();
+
+        let options = OutputOptions {
+            output_path: "test/corrupted_restored".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let (restored, rejected) = processor.clone().decode(&meta.encoder_parameters, &path, &String::new(), &meta.file_hash, &options).unwrap();
+
+        assert_eq!(rejected.len(), 3);
+        assert_eq!(fs::read(&restored).unwrap(), fs::read("test/10_000").unwrap());
+
+        // Restored file doesn't match the expected hash - nothing is written
+        let options = OutputOptions {
+            output_path: "test/mismatch_restored".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let _ = fs::remove_file(&options.output_path);
+        let wrong_hash = RaptorQProcessor::symbols_id(&b"another file".to_vec());
+        assert!(processor.decode(&meta.encoder_parameters, &path, &String::new(), &wrong_hash, &options).is_err());
+        assert!(!Path::new(&options.output_path).exists());
+    }
+
+    #[test]
+    fn rq_test_decode_checks_manifest_hash() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12);
+
+        let options = OutputOptions {
+            object_id: "manifest_hash".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let (meta, path) = processor.encode(&String::from("test/10_000"), &options).unwrap();
+
+        // The manifest hash is checked even with explicit encoder parameters
+        let manifest_path = manifest_path(Path::new(&path));
+        let mut manifest: SymbolsManifest = serde_json::from_slice(&fs::read(&manifest_path).unwrap()).unwrap();
+        manifest.file_hash = RaptorQProcessor::symbols_id(&b"another file".to_vec());
+        fs::write(&manifest_path, serde_json::to_string(&manifest).unwrap()).unwrap();
+
+        let options = OutputOptions {
+            output_path: "test/manifest_hash_restored".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let _ = fs::remove_file(&options.output_path);
+        let err = processor.clone()
+            .decode(&meta.encoder_parameters, &path, &String::new(), &String::new(), &options)
+            .unwrap_err();
+        assert_eq!(err.kind(), RqErrorKind::HashMismatch);
+        assert!(!Path::new(&options.output_path).exists());
+
+        // An explicit hash wins over the manifest
+        let (restored, _rejected) = processor
+            .decode(&meta.encoder_parameters, &path, &String::new(), &meta.file_hash, &options)
+            .unwrap();
+        assert_eq!(fs::read(&restored).unwrap(), fs::read("test/10_000").unwrap());
+    }
+
+    #[test]
+    fn rq_test_encode_with_metadata() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12);
+
+        let options = OutputOptions {
+            object_id: "combined".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        // RQ IDs files get new names every run, start without the ones of earlier runs
+        let _ = fs::remove_dir_all("test/meta/combined");
+        let (meta, symbols_path, meta_path) = processor.encode_with_metadata(&String::from("test/10_000"), 2,
+                                                                             &String::from("12345"), &String::from("67890"),
+                                                                             &options).unwrap();
+
+        let mut symbols: Vec<String> = fs::read_dir(&symbols_path).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        symbols.sort();
+        assert_eq!(symbols.len() as u32, meta.source_symbols + meta.repair_symbols);
+
+        for entry in fs::read_dir(&meta_path).unwrap() {
+            let rq_ids_file = RqIdsFile::load(&entry.unwrap().path()).unwrap();
+            assert_eq!(rq_ids_file.version, crate::rqids::RQ_IDS_VERSION);
+            assert_eq!(rq_ids_file.file_name.as_deref(), Some("10_000"));
+            assert_eq!(rq_ids_file.file_size, Some(10_000));
+            assert_eq!(rq_ids_file.encoder_parameters.as_ref(), Some(&meta.encoder_parameters));
+            assert_eq!(rq_ids_file.file_hash, meta.file_hash);
+            let mut ids = rq_ids_file.symbol_identifiers;
+            ids.sort();
+            assert_eq!(ids, symbols);
+        }
+
+        // The RQ IDs file is enough to decode, without the manifest
+        fs::remove_file(manifest_path(Path::new(&symbols_path))).unwrap();
+        let rq_ids_path = fs::read_dir(&meta_path).unwrap().next().unwrap().unwrap().path();
+        let options = OutputOptions {
+            output_path: "test/combined_restored".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let (restored, _rejected) = processor.decode(&Vec::new(), &symbols_path,
+                                                     &rq_ids_path.to_string_lossy().to_string(),
+                                                     &String::new(), &options).unwrap();
+        assert_eq!(fs::read(&restored).unwrap(), fs::read("test/10_000").unwrap());
+    }
+
+    #[test]
+    fn rq_test_progress_events() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let collected = events.clone();
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12).with_progress(move |event| collected.lock().unwrap().push(event));
+
+        let options = OutputOptions {
+            object_id: "progress".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let (meta, path) = processor.encode(&String::from("test/10_000"), &options).unwrap();
+        let symbols_count = (meta.source_symbols + meta.repair_symbols) as u64;
+
+        let encoded: Vec<ProgressEvent> = events.lock().unwrap().drain(..).collect();
+        assert_eq!(encoded.first(), Some(&ProgressEvent::BytesRead { read: 10_000, total: 10_000 }));
+        assert_eq!(encoded.last(), Some(&ProgressEvent::SymbolsWritten { written: symbols_count, total: symbols_count }));
+        assert_eq!(encoded.len() as u64, symbols_count + 1);
+
+        let options = OutputOptions {
+            output_path: "test/progress_restored".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        processor.decode(&meta.encoder_parameters, &path, &String::new(), &String::new(), &options).unwrap();
+
+        let decoded = events.lock().unwrap();
+        assert_eq!(decoded.first(), Some(&ProgressEvent::SymbolsFed { fed: 1, total: symbols_count }));
+        assert_eq!(decoded.last(), Some(&ProgressEvent::BytesRestored { restored: 10_000, total: 10_000 }));
+    }
+
+    #[test]
+    fn rq_test_each_symbol() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12);
+
+        let mut encoded = Vec::new();
+        let mut encoded_size = 0u64;
+        let meta = processor.encode_each_symbol(&String::from("test/10_000"), |id, payload_id, data| {
+            assert_eq!(id, RaptorQProcessor::symbols_id(&data));
+            encoded.push((id.to_string(), payload_id));
+            encoded_size += data.len() as u64;
+            Ok(())
+        }).unwrap();
+        assert_eq!(encoded.len() as u32, meta.source_symbols + meta.repair_symbols);
+        assert_eq!(processor.encoded_size(&String::from("test/10_000")).unwrap(), encoded_size);
+
+        let options = OutputOptions {
+            object_id: "each_symbol".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let (_meta, path) = processor.encode(&String::from("test/10_000"), &options).unwrap();
+        fs::write(Path::new(&path).join("unrelated"), b"not a symbol").unwrap();
+
+        let mut read = Vec::new();
+        let (manifest, rejected) = processor.read_each_symbol(&path, |id, payload_id, _data| {
+            read.push((id.to_string(), payload_id));
+            Ok(())
+        }).unwrap();
+        assert_eq!(manifest.unwrap().encoder_parameters, meta.encoder_parameters);
+        assert_eq!(rejected, vec!["unrelated".to_string()]);
+
+        encoded.sort_by(|a, b| a.0.cmp(&b.0));
+        read.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(read, encoded);
+    }
+
+    #[test]
+    fn rq_test_decode_packets() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12);
+
+        let mut packets = Vec::new();
+        let meta = processor.encode_each_symbol(&String::from("test/10_000_000"), |_id, _payload_id, data| {
+            packets.push(data);
+            Ok(())
+        }).unwrap();
+        // Repair symbols only, the decoder stops taking packets once the file is restored
+        packets.reverse();
+
+        let options = OutputOptions {
+            output_path: "test/packets_restored".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let (restored, received, rejected) = processor.clone().decode_packets(&meta.encoder_parameters, packets.iter().cloned(),
+                                                                             &meta.file_hash, &options).unwrap();
+        assert!(received < packets.len() as u32);
+        assert_eq!(rejected, 0);
+        assert_eq!(RaptorQProcessor::file_hash("test", Path::new(&restored)).unwrap(), meta.file_hash);
+
+        // The same packets in batches
+        let mut dec = processor.clone().packets_decoder(&meta.encoder_parameters, &meta.file_hash, &options).unwrap();
+        for batch in packets.chunks(64) {
+            if dec.add_packets(batch.to_vec()).unwrap() {
+                break;
+            }
+        }
+        assert_eq!(dec.finish().unwrap(), (restored, received, rejected));
+
+        // An unfinished decoder removes its partial output
+        let mut dec = processor.clone().packets_decoder(&meta.encoder_parameters, &meta.file_hash, &options).unwrap();
+        assert!(!dec.add_packets(packets[..64].to_vec()).unwrap());
+        drop(dec);
+        assert!(!Path::new("test/packets_restored.partial").exists());
+
+        let options = OutputOptions {
+            output_path: "test/packets_not_restored".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let _ = fs::remove_file(&options.output_path);
+        let few = packets.iter().take(meta.source_symbols as usize / 2).cloned();
+        assert!(processor.clone().decode_packets(&meta.encoder_parameters, few, &meta.file_hash, &options).is_err());
+        assert!(!Path::new(&options.output_path).exists());
+
+        // Zero source blocks - rejected before a decoder is built
+        let mut encoder_parameters = meta.encoder_parameters.clone();
+        encoder_parameters[8] = 0;
+        assert!(processor.decode_packets(&encoder_parameters, packets.iter().cloned(), &meta.file_hash, &options).is_err());
+        assert!(!Path::new(&options.output_path).exists());
+    }
+
+    #[test]
+    fn rq_test_repair_symbols() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12)
+            .with_work_dir("test/work");
+
+        let options = OutputOptions {
+            object_id: "repair".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let _ = fs::remove_dir_all("test/meta/repair");
+        let (meta, symbols_path, meta_path) = processor.encode_with_metadata(&String::from("test/10_000_000"), 1,
+                                                                             &String::from("12345"), &String::from("67890"),
+                                                                             &options).unwrap();
+        let mut names: Vec<String> = fs::read_dir(&symbols_path).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+
+        // From the manifest next to the symbols - two lost and one damaged
+        let symbols = Path::new(&symbols_path);
+        fs::remove_file(symbols.join(&names[0])).unwrap();
+        fs::remove_file(symbols.join(&names[1])).unwrap();
+        fs::write(symbols.join(&names[2]), b"damaged").unwrap();
+        let mut regenerated = processor.repair_symbols(&Vec::new(), &symbols_path, &String::new()).unwrap();
+        regenerated.sort();
+        assert_eq!(regenerated, names[..3].to_vec());
+        for name in &names[..3] {
+            assert_eq!(RaptorQProcessor::symbols_id(&fs::read(symbols.join(name)).unwrap()), *name);
+        }
+
+        // From the RQ IDs file, which has the encoder parameters too
+        let rq_ids_file = fs::read_dir(&meta_path).unwrap().next().unwrap().unwrap()
+            .path().to_str().unwrap().to_string();
+        fs::remove_file(symbols.join(&names[3])).unwrap();
+        let regenerated = processor.repair_symbols(&Vec::new(), &symbols_path, &rq_ids_file).unwrap();
+        assert_eq!(regenerated, vec![names[3].clone()]);
+        fs::remove_file(symbols.join(&names[4])).unwrap();
+        let regenerated = processor.repair_symbols(&meta.encoder_parameters, &symbols_path, &rq_ids_file).unwrap();
+        assert_eq!(regenerated, vec![names[4].clone()]);
+
+        assert!(processor.repair_symbols(&Vec::new(), &symbols_path, &String::new()).unwrap().is_empty());
+        // Restored files are only kept while repairing
+        assert_eq!(fs::read_dir("test/work/repair").unwrap().count(), 0);
+    }
+
+    #[test]
+    fn rq_test_verify_metadata() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12);
+
+        let options = OutputOptions {
+            object_id: "verify".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let _ = fs::remove_dir_all("test/meta/verify");
+        let (_meta, meta_path) = processor.create_metadata(&String::from("test/10_000"), 1,
+                                                           &String::from("12345"), &String::from("67890"),
+                                                           &options).unwrap();
+        let rq_ids_path = fs::read_dir(&meta_path).unwrap().next().unwrap().unwrap().path();
+        let rq_ids_file = rq_ids_path.to_str().unwrap().to_string();
+
+        let verification = processor.verify_metadata(&String::from("test/10_000"), &rq_ids_file,
+                                                     &String::from("12345"), &String::from("67890")).unwrap();
+        assert!(verification.symbols_match && verification.block_hash_matches && verification.pastel_id_matches);
+
+        // Another file, block hash and redundancy
+        let other = RaptorQProcessor::new(
+            50_000,
+            6);
+        let verification = other.verify_metadata(&String::from("test/10_000_000"), &rq_ids_file,
+                                                 &String::from("12345"), &String::from("other")).unwrap();
+        assert!(!verification.symbols_match);
+        assert!(verification.block_hash_matches);
+        assert!(!verification.pastel_id_matches);
+        assert_eq!(verification.unexpected_symbols.len(), 13);
+        assert_eq!(verification.missing_symbols.len(), 200 * 6);
+    }
+
+    #[test]
+    fn rq_test_check_symbols() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12);
+
+        let options = OutputOptions {
+            object_id: "check".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        // Symbols of another object are added below, start from an empty directory
+        let _ = fs::remove_dir_all("test/symbols/check");
+        let (meta, symbols_path) = processor.encode(&String::from("test/10_000_000"), &options).unwrap();
+        let mut names: Vec<String> = fs::read_dir(&symbols_path).unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+
+        let check = processor.check_symbols(&Vec::new(), &symbols_path, &String::new()).unwrap();
+        assert_eq!(check.valid.len(), names.len());
+        assert_eq!(check.symbols_needed, 0);
+
+        // Just as many valid symbols as source symbols are not counted on to decode the block
+        let symbols = Path::new(&symbols_path);
+        for name in &names[200..] {
+            fs::remove_file(symbols.join(name)).unwrap();
+        }
+        let check = processor.check_symbols(&Vec::new(), &symbols_path, &String::new()).unwrap();
+        assert_eq!(check.symbols_needed, 2);
+
+        // Keep 150 symbols only, damage one of them and add a symbol of another object
+        for name in &names[150..200] {
+            fs::remove_file(symbols.join(name)).unwrap();
+        }
+        fs::write(symbols.join(&names[0]), b"damaged").unwrap();
+        fs::create_dir_all(symbols.join("extracted")).unwrap();
+        let other = processor.encode_each_symbol(&String::from("test/10_000"), |id, _payload_id, data| {
+            fs::write(symbols.join(id), &data).map_err(RqProcessorError::from)
+        }).unwrap();
+
+        let check = processor.check_symbols(&meta.encoder_parameters, &symbols_path, &String::new()).unwrap();
+        assert_eq!(check.valid.len(), 149);
+        assert_eq!(check.missing.len(), names.len() - 150);
+        assert_eq!(check.corrupted, vec![names[0].clone()]);
+        assert_eq!(check.unlisted.len() as u32, other.source_symbols + other.repair_symbols);
+        assert_eq!(check.symbols_needed, 200 + 2 - 149);
+    }
+
+    #[test]
+    fn rq_test_pastel_rq_ids() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12)
+            .with_rq_ids_mode(RqIdsMode::Pastel {
+                signer: RqIdsSigner::Hook(Arc::new(|_data| Ok(b"signature".to_vec()))),
+                initial_counter: 7 });
+
+        let options = OutputOptions {
+            object_id: "pastel".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let (meta, path) = processor.create_metadata(&String::from("test/10_000"), 3,
+                                                     &String::from("12345"), &String::from("67890"),
+                                                     &options).unwrap();
+        assert_eq!(meta.rq_ids.len(), 3);
+        assert_eq!(meta.rq_ids_counters, Some((7, 9)));
+
+        for (n, id) in meta.rq_ids.iter().enumerate() {
+            let content = fs::read(Path::new(&path).join(id)).unwrap();
+            assert_eq!(id, &RaptorQProcessor::symbols_id(&content));
+
+            let decoded = String::from_utf8(zstd::decode_all(&content[..]).unwrap()).unwrap();
+            let parts: Vec<&str> = decoded.split('.').collect();
+            assert_eq!(parts.len(), 3);
+            let rq_ids_file: RqIdsFile = serde_json::from_slice(&base64::decode(parts[0]).unwrap()).unwrap();
+            assert_eq!(rq_ids_file.block_hash, "12345");
+            assert_eq!(base64::decode(parts[1]).unwrap(), b"signature");
+            assert_eq!(parts[2], (7 + n).to_string());
+        }
+    }
+
+    #[test]
+    fn rq_test_signed_rq_ids() {
+        let secret = [7u8; 32];
+        fs::write("test/ids_signing_key", &secret).unwrap();
+        let public_key = PublicKey::from(&SecretKey::from_bytes(&secret).unwrap()).to_bytes();
+        let other_key = PublicKey::from(&SecretKey::from_bytes(&[8u8; 32]).unwrap()).to_bytes();
+        let signer = RqIdsSigner::from_key_file(&String::from("test/ids_signing_key")).unwrap();
+
+        let options = OutputOptions {
+            object_id: "signed".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12)
+            .with_rq_ids_mode(RqIdsMode::Uuid { signer: Some(signer.clone()) });
+        let (meta, path) = processor.create_metadata(&String::from("test/10_000"), 2,
+                                                     &String::from("12345"), &String::from("67890"),
+                                                     &options).unwrap();
+        let rq_ids_path = Path::new(&path).join(&meta.rq_ids[0]).to_string_lossy().to_string();
+        let verification = RaptorQProcessor::verify_ids_file(&rq_ids_path, &public_key).unwrap();
+        assert!(verification.signed && verification.signature_valid);
+        assert_eq!(verification.block_hash, "12345");
+        assert_eq!(verification.pastel_id, "67890");
+        assert_eq!(verification.symbols_count, meta.source_symbols + meta.repair_symbols);
+        assert_eq!(verification.counter, None);
+        assert!(!RaptorQProcessor::verify_ids_file(&rq_ids_path, &other_key).unwrap().signature_valid);
+
+        // Any change of the content breaks the signature
+        let tampered = fs::read_to_string(&rq_ids_path).unwrap().replace("67890", "00000");
+        fs::write(&rq_ids_path, tampered).unwrap();
+        let verification = RaptorQProcessor::verify_ids_file(&rq_ids_path, &public_key).unwrap();
+        assert!(verification.signed && !verification.signature_valid);
+
+        let processor = processor.with_rq_ids_mode(RqIdsMode::Pastel { signer, initial_counter: 1 });
+        let (meta, path) = processor.create_metadata(&String::from("test/10_000"), 1,
+                                                     &String::from("12345"), &String::from("67890"),
+                                                     &options).unwrap();
+        let rq_ids_path = Path::new(&path).join(&meta.rq_ids[0]).to_string_lossy().to_string();
+        let verification = RaptorQProcessor::verify_ids_file(&rq_ids_path, &public_key).unwrap();
+        assert!(verification.signed && verification.signature_valid);
+        assert_eq!(verification.counter, Some(1));
+    }
+
+    #[test]
+    fn rq_test_compressed_rq_ids() {
+        let options = OutputOptions {
+            object_id: "compressed".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        for compression in &[RqIdsCompression::Zstd, RqIdsCompression::Gzip] {
+            let processor = RaptorQProcessor::new(
+                50_000,
+                12)
+                .with_rq_ids_compression(*compression);
+            let (meta, symbols_path, meta_path) = processor.encode_with_metadata(&String::from("test/10_000"), 1,
+                                                                                 &String::from("12345"), &String::from("67890"),
+                                                                                 &options).unwrap();
+            let rq_ids_path = Path::new(&meta_path).join(&meta.rq_ids[0]).to_string_lossy().to_string();
+            assert!(serde_json::from_slice::<RqIdsFile>(&fs::read(&rq_ids_path).unwrap()).is_err());
+
+            let verification = processor.verify_metadata(&String::from("test/10_000"), &rq_ids_path,
+                                                         &String::from("12345"), &String::from("67890")).unwrap();
+            assert!(verification.symbols_match);
+            let check = processor.check_symbols(&Vec::new(), &symbols_path, &rq_ids_path).unwrap();
+            assert_eq!(check.valid.len() as u32, meta.source_symbols + meta.repair_symbols);
+        }
+    }
+
+    #[test]
+    fn rq_test_deterministic_rq_ids() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12)
+            .with_rq_ids_compression(RqIdsCompression::Gzip)
+            .with_deterministic_rq_ids(true);
+
+        let options = OutputOptions {
+            object_id: "deterministic".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let _ = fs::remove_dir_all("test/meta/deterministic");
+        let (meta, path) = processor.create_metadata(&String::from("test/10_000"), 3,
+                                                     &String::from("12345"), &String::from("67890"),
+                                                     &options).unwrap();
+        assert_eq!(meta.rq_ids.iter().collect::<HashSet<_>>().len(), 3);
+        let files: Vec<Vec<u8>> = meta.rq_ids.iter()
+            .map(|id| fs::read(Path::new(&path).join(id)).unwrap())
+            .collect();
+
+        // The same files again, even without overwrite
+        let options = OutputOptions { overwrite: false, ..options };
+        let (again, _path) = processor.create_metadata(&String::from("test/10_000"), 3,
+                                                       &String::from("12345"), &String::from("67890"),
+                                                       &options).unwrap();
+        assert_eq!(again.rq_ids, meta.rq_ids);
+        for (id, data) in again.rq_ids.iter().zip(&files) {
+            assert_eq!(&fs::read(Path::new(&path).join(id)).unwrap(), data);
+        }
+        assert_eq!(fs::read_dir(&path).unwrap().count(), 3);
+
+        let (other, _path) = processor.create_metadata(&String::from("test/10_000"), 1,
+                                                       &String::from("12345"), &String::from("other"),
+                                                       &options).unwrap();
+        assert!(!meta.rq_ids.contains(&other.rq_ids[0]));
+
+        // An upload stored under a temporary name gives the same files with the original name
+        fs::copy("test/10_000", "test/upload-deterministic").unwrap();
+        let (upload, _path) = processor.clone()
+            .with_rq_ids_file_name(Some("10_000".to_string()))
+            .create_metadata(&String::from("test/upload-deterministic"), 3,
+                             &String::from("12345"), &String::from("67890"), &options).unwrap();
+        assert_eq!(upload.rq_ids, meta.rq_ids);
+
+        let (unnamed, _path) = processor
+            .with_rq_ids_file_name(None)
+            .create_metadata(&String::from("test/upload-deterministic"), 1,
+                             &String::from("12345"), &String::from("67890"), &options).unwrap();
+        let rq_ids_file = RqIdsFile::load(&Path::new(&path).join(&unnamed.rq_ids[0])).unwrap();
+        assert_eq!(rq_ids_file.file_name, None);
+        let _ = fs::remove_file("test/upload-deterministic");
+    }
+
+    #[test]
+    fn rq_test_container() {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12);
+
+        let options = OutputOptions {
+            object_id: "container".to_string(),
+            overwrite: true,
+            container: true,
+            ..Default::default() };
+        let (meta, container_path) = processor.encode(&String::from("test/10_000_000"), &options).unwrap();
+        assert_eq!(container_path, "test/symbols/container.rqc");
+
+        let listing = RaptorQProcessor::list_container(&container_path).unwrap();
+        assert_eq!(listing.encoder_parameters, meta.encoder_parameters);
+        assert_eq!(listing.file_hash, meta.file_hash);
+        assert_eq!(listing.symbols.len() as u32, meta.source_symbols + meta.repair_symbols);
+
+        // Damage the first symbol, the others are enough to decode
+        let mut data = fs::read(&container_path).unwrap();
+        data[listing.symbols[0].offset as usize + 10] ^= 0xff;
+        fs::write(&container_path, &data).unwrap();
+
+        let options = OutputOptions {
+            output_path: "test/container_restored".to_string(),
+            overwrite: true,
+            ..Default::default() };
+        let (restored, rejected) = RaptorQProcessor::new(50_000, 12)
+            .decode(&Vec::new(), &container_path, &String::new(), &String::new(), &options).unwrap();
+        assert_eq!(rejected, vec![listing.symbols[0].id.clone()]);
+        assert_eq!(RaptorQProcessor::file_hash("test", Path::new(&restored)).unwrap(), meta.file_hash);
+
+        // The hash in an RQ IDs file given by the caller is checked too
+        let config = RaptorQProcessor::parse_encoder_parameters("test", &meta.encoder_parameters).unwrap();
+        let mut rq_ids_file = RqIdsFile::new(&config, &RaptorQProcessor::symbols_id(&b"another file".to_vec()), "b", "c");
+        rq_ids_file.symbol_identifiers = listing.symbols.iter().map(|entry| entry.id.clone()).collect();
+        fs::write("test/container_rq_ids", serde_json::to_vec(&rq_ids_file).unwrap()).unwrap();
+        let err = RaptorQProcessor::new(50_000, 12)
+            .decode(&Vec::new(), &container_path, &String::from("test/container_rq_ids"), &String::new(), &options)
+            .unwrap_err();
+        assert_eq!(err.kind(), RqErrorKind::HashMismatch);
+        fs::remove_file("test/container_rq_ids").unwrap();
+
+        // Other files are not taken for containers
+        assert!(RaptorQProcessor::new(50_000, 12)
+            .decode(&Vec::new(), &String::from("test/10_000"), &String::new(), &String::new(), &options).is_err());
+
+        let ids: Vec<String> = listing.symbols[1..4].iter().map(|entry| entry.id.clone()).collect();
+        let _ = fs::remove_dir_all("test/symbols/container");
+        let (symbols_path, extracted) = processor.extract_symbols(&container_path, &ids, &String::new(), false).unwrap();
+        assert_eq!(symbols_path, "test/symbols/container");
+        assert_eq!(extracted, ids);
+        for id in &ids {
+            assert_eq!(RaptorQProcessor::symbols_id(&fs::read(Path::new(&symbols_path).join(id)).unwrap()), *id);
+        }
+        assert!(processor.extract_symbols(&container_path, &[listing.symbols[0].id.clone()], &String::new(), false).is_err());
+        assert!(processor.extract_symbols(&container_path, &[String::from("unknown")], &String::new(), false).is_err());
+    }
 }
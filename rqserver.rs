@@ -4,19 +4,303 @@
 
 use crate::app::ServiceSettings;
 
-use tonic::{transport::Server, Request, Response, Status};
+use std::sync::{Arc, TryLockError};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio::io::AsyncWriteExt;
+use tonic::{transport::Server, Request, Response, Status, Streaming};
 
 pub mod rq {
     tonic::include_proto!("raptorq");
 }
 use rq::raptor_q_server::{RaptorQ, RaptorQServer};
-use rq::{EncodeMetaDataRequest, EncodeMetaDataReply, EncodeRequest, EncodeReply, DecodeRequest, DecodeReply};
+use rq::{EncodeMetaDataRequest, EncodeMetaDataReply, EncodeRequest, EncodeReply, DecodeRequest, DecodeReply,
+         EncodeWithMetaDataRequest, EncodeWithMetaDataReply, JobReply, JobRequest, JobStatusReply,
+         EncodeProgressReply, DecodeProgressReply, EncodeStreamRequest, EncodeStreamReply,
+         StreamSymbolsRequest, StreamSymbolsReply, DecodeStreamRequest, DecodeStreamReply,
+         OpenDecodeSessionRequest, AddSymbolsRequest, SessionRequest, SessionStatusReply,
+         RepairSymbolsRequest, RepairSymbolsReply, VerifyMetadataRequest, VerifyMetadataReply,
+         CheckSymbolsRequest, CheckSymbolsReply, VerifyIdsFileRequest, VerifyIdsFileReply,
+         ListContainerSymbolsRequest, ListContainerSymbolsReply,
+         ExtractContainerSymbolsRequest, ExtractContainerSymbolsReply};
 
+use crate::jobs::{JobManager, JobResult, JobState, JobStatus};
 use crate::rqprocessor;
+use crate::rqids::RqIdsCompression;
+use crate::rqprocessor::{JobControl, ProgressEvent, RqErrorKind};
+use crate::sessions::{DecodeSession, SessionManager, SessionStatus};
+use crate::uploads::UploadedFile;
+use crate::workers::WorkerPool;
 
-#[derive(Debug, Default)]
+// Progress messages waiting to be sent to a slow client, further ones are dropped while it is full
+const PROGRESS_CHANNEL_SIZE: usize = 64;
+// Size of the restored file pieces sent back by DecodeStream
+const DATA_CHUNK_SIZE: usize = 1024 * 1024;
+// Most symbols DecodeStream passes to the decoder in one worker pool job
+const DECODE_BATCH_SIZE: usize = 64;
+// Symbols returned in the EncodeStream reply, well below tonic's default 4 MB message limit.
+// Larger outputs are stored and only their location is returned, for StreamSymbols.
+const MAX_RETURNED_SYMBOLS_SIZE: u64 = 3 * 1024 * 1024;
+
+#[derive(Debug)]
 pub struct RaptorQService {
     pub settings: ServiceSettings,
+    workers: WorkerPool,
+    jobs: JobManager,
+    sessions: SessionManager,
+    ids_signer: Option<rqprocessor::RqIdsSigner>,
+}
+
+impl RaptorQService {
+    pub fn new(settings: &ServiceSettings) -> Result<Self, rqprocessor::RqProcessorError> {
+        let workers = WorkerPool::new(settings.max_jobs, settings.max_queued_jobs);
+        let ids_signer = if settings.ids_signing_key.is_empty() {
+            None
+        } else {
+            Some(rqprocessor::RqIdsSigner::from_key_file(&settings.ids_signing_key)?)
+        };
+        Ok(RaptorQService {
+            settings: settings.clone(),
+            jobs: JobManager::new(workers.clone()),
+            workers,
+            sessions: SessionManager::new(std::time::Duration::from_secs(settings.session_timeout),
+                                          settings.max_sessions),
+            ids_signer,
+        })
+    }
+
+    // Ids files are signed with the local key when asked for, once their content is generated
+    fn rq_ids_signer(&self, sign_ids: bool) -> Result<Option<rqprocessor::RqIdsSigner>, Status> {
+        if !sign_ids {
+            return Ok(None);
+        }
+        match &self.ids_signer {
+            Some(signer) => Ok(Some(signer.clone())),
+            None => Err(Status::failed_precondition("ids-signing-key is not configured"))
+        }
+    }
+
+    // A panic while adding symbols leaves the session in an unknown state, it cannot be used any more
+    fn drop_poisoned_session(&self, session_id: &str) -> Status {
+        log::error!("Decode session {} is poisoned, dropping it", session_id);
+        self.sessions.remove(session_id);
+        Status::internal("Internal error")
+    }
+
+    fn rq_ids_compression(&self, compression: rq::IdsCompression) -> RqIdsCompression {
+        match compression {
+            rq::IdsCompression::DefaultCompression => self.settings.rq_ids_compression,
+            rq::IdsCompression::NoCompression => RqIdsCompression::None,
+            rq::IdsCompression::Zstd => RqIdsCompression::Zstd,
+            rq::IdsCompression::Gzip => RqIdsCompression::Gzip,
+        }
+    }
+
+    // Pastel ids files when asked for, they have to be signed
+    fn rq_ids_mode(&self, pastel_ids: bool, sign_ids: bool, initial_counter: u32)
+        -> Result<rqprocessor::RqIdsMode, Status> {
+
+        let signer = self.rq_ids_signer(sign_ids)?;
+        if !pastel_ids {
+            return Ok(rqprocessor::RqIdsMode::Uuid { signer });
+        }
+        match signer {
+            Some(signer) => Ok(rqprocessor::RqIdsMode::Pastel { signer, initial_counter }),
+            None => Err(Status::invalid_argument("sign_ids is required for Pastel ids files"))
+        }
+    }
+}
+
+// A restored file that doesn't match the original is reported as such, anything else stays internal
+fn error_status(e: rqprocessor::RqProcessorError) -> Status {
+    match e.kind() {
+        RqErrorKind::HashMismatch => {
+            log::error!("Restored file doesn't match: {:?}", e);
+            Status::data_loss(e.to_string())
+        },
+        RqErrorKind::Internal => {
+            log::error!("Internal error: {:?}", e);
+            Status::internal("Internal error")
+        }
+    }
+}
+
+fn encoder_job_result(meta: rqprocessor::EncoderMetaData, paths: Vec<String>) -> JobResult {
+    JobResult {
+        paths,
+        encoder_parameters: meta.encoder_parameters,
+        symbols_count: meta.source_symbols+meta.repair_symbols,
+        file_hash: meta.file_hash,
+        ..Default::default() }
+}
+
+fn progress_reply(event: ProgressEvent) -> rq::Progress {
+    let (stage, processed, total) = match event {
+        ProgressEvent::BytesRead { read, total } => (rq::ProgressStage::BytesRead, read, total),
+        ProgressEvent::SymbolsWritten { written, total } => (rq::ProgressStage::SymbolsWritten, written, total),
+        ProgressEvent::SymbolsFed { fed, total } => (rq::ProgressStage::SymbolsFed, fed, total),
+        ProgressEvent::BytesRestored { restored, total } => (rq::ProgressStage::BytesRestored, restored, total),
+    };
+    rq::Progress { stage: stage as i32, processed, total }
+}
+
+// Forwards progress events to the stream, and cancels the request once the client has gone away.
+// Events a slow client has no room for are dropped, so the job never waits for it -
+// only the final result is sure to be delivered.
+fn streaming_processor<T, F>(settings: &ServiceSettings, tx: mpsc::Sender<Result<T, Status>>, f: F)
+    -> rqprocessor::RaptorQProcessor
+    where T: Send + 'static,
+          F: Fn(rq::Progress) -> T + Send + Sync + 'static {
+
+    let control = Arc::new(JobControl::default());
+    let stream_control = control.clone();
+    rqprocessor::RaptorQProcessor::new(
+        settings.symbol_size,
+        settings.redundancy_factor)
+        .with_control(control)
+        .with_progress(move |event| {
+            if let Err(mpsc::error::TrySendError::Closed(_)) = tx.try_send(Ok(f(progress_reply(event)))) {
+                stream_control.cancel();
+            }
+        })
+}
+
+fn encode_stream_reply(meta: rqprocessor::EncoderMetaData, symbols_path: String, meta_path: String,
+                       symbols: Vec<rq::Symbol>) -> EncodeStreamReply {
+    let (first_counter, last_counter) = meta.rq_ids_counters.unwrap_or_default();
+    rq::EncodeStreamReply {
+        encoder_parameters: meta.encoder_parameters,
+        symbols_count: meta.source_symbols+meta.repair_symbols,
+        symbols_path,
+        meta_path,
+        file_hash: meta.file_hash,
+        symbols,
+        rq_ids: meta.rq_ids,
+        first_counter,
+        last_counter }
+}
+
+// Stores the file content sent after the header
+async fn receive_upload(work_dir: &str, stream: &mut Streaming<EncodeStreamRequest>) -> Result<UploadedFile, Status> {
+
+    let (upload, mut file) = match UploadedFile::create(work_dir).await {
+        Ok(upload) => upload,
+        Err(err) => {
+            log::error!("Cannot create uploaded file in {} - {}", work_dir, err);
+            return Err(Status::internal("Internal error"));
+        }
+    };
+
+    while let Some(message) = stream.message().await? {
+        let chunk = match message.data {
+            Some(rq::encode_stream_request::Data::Chunk(chunk)) => chunk,
+            _ => return Err(Status::invalid_argument("Only file content is expected after the header"))
+        };
+        if let Err(err) = file.write_all(&chunk).await {
+            log::error!("Cannot write uploaded file {:?} - {}", upload.path(), err);
+            return Err(Status::internal("Internal error"));
+        }
+    }
+    if let Err(err) = file.flush().await {
+        log::error!("Cannot write uploaded file {:?} - {}", upload.path(), err);
+        return Err(Status::internal("Internal error"));
+    }
+
+    Ok(upload)
+}
+
+fn symbol_reply(id: &str, payload_id: raptorq::PayloadId, data: Vec<u8>) -> StreamSymbolsReply {
+    let symbol = rq::SymbolPacket {
+        id: id.to_string(),
+        source_block_number: payload_id.source_block_number() as u32,
+        encoding_symbol_id: payload_id.encoding_symbol_id(),
+        data };
+    StreamSymbolsReply { event: Some(rq::stream_symbols_reply::Event::Symbol(symbol)) }
+}
+
+// Feeds the symbols received by DecodeStream to the decoder, in batches of the ones that have arrived.
+// Every batch is a worker pool job of its own, so waiting for a slow client never holds a worker slot.
+async fn decode_received_packets(workers: &WorkerPool, mut decoder: rqprocessor::PacketsDecoder,
+                                 packets_rx: &mut mpsc::Receiver<Vec<u8>>)
+    -> Result<(String, u32, u32), Status> {
+
+    while let Some(data) = packets_rx.recv().await {
+        let mut batch = vec![data];
+        while batch.len() < DECODE_BATCH_SIZE {
+            match packets_rx.try_recv() {
+                Ok(data) => batch.push(data),
+                Err(_) => break
+            }
+        }
+        let (returned, restored) = workers.run(move || {
+            let restored = decoder.add_packets(batch);
+            (decoder, restored)
+        }).await?;
+        decoder = returned;
+        if restored.map_err(error_status)? {
+            break;
+        }
+    }
+    workers.run(move || decoder.finish()).await?.map_err(error_status)
+}
+
+// Sends the restored file back in chunks, and removes it when it was only kept for this
+fn send_restored_file(path: &str, remove: bool, tx: &mpsc::Sender<Result<DecodeStreamReply, Status>>)
+    -> Result<(), rqprocessor::RqProcessorError> {
+
+    let mut file = std::fs::File::open(path)?;
+    let mut buf = vec![0u8; DATA_CHUNK_SIZE];
+    let result = loop {
+        let len = match std::io::Read::read(&mut file, &mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(len) => len,
+            Err(err) => break Err(err.into())
+        };
+        let reply = DecodeStreamReply { event: Some(rq::decode_stream_reply::Event::Chunk(buf[..len].to_vec())) };
+        if tx.blocking_send(Ok(reply)).is_err() {
+            break Err(rqprocessor::RqProcessorError::new("decode_stream", "Client has gone away", "".to_string()));
+        }
+    };
+    if remove {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+fn session_status_reply(session_id: String, status: SessionStatus) -> SessionStatusReply {
+    rq::SessionStatusReply {
+        session_id,
+        symbols_received: status.symbols_received,
+        symbols_rejected: status.symbols_rejected,
+        symbols_needed: status.symbols_needed,
+        restored: !status.path.is_empty(),
+        path: status.path,
+        error: status.error,
+        expires_in_secs: status.expires_in.as_secs() }
+}
+
+fn job_status_reply(job_id: String, status: JobStatus) -> JobStatusReply {
+    let state = match status.state {
+        JobState::Queued => rq::JobState::Queued,
+        JobState::Running => rq::JobState::Running,
+        JobState::Completed => rq::JobState::Completed,
+        JobState::Failed => rq::JobState::Failed,
+        JobState::Cancelled => rq::JobState::Cancelled,
+    };
+    let result = status.result.unwrap_or_default();
+
+    rq::JobStatusReply {
+        job_id,
+        kind: status.kind,
+        state: state as i32,
+        processed: status.processed,
+        total: status.total,
+        paths: result.paths,
+        encoder_parameters: result.encoder_parameters,
+        symbols_count: result.symbols_count,
+        file_hash: result.file_hash,
+        rejected_symbols: result.rejected_symbols,
+        error: status.error }
 }
 
 #[tonic::async_trait]
@@ -24,26 +308,39 @@ impl RaptorQ for RaptorQService {
     async fn encode_meta_data(&self, request: Request<EncodeMetaDataRequest>) -> Result<Response<EncodeMetaDataReply>, Status> {
         log::info!("Got a 'encoder_info' request: {:?}", request);
 
+        let req = request.into_inner();
         let processor = rqprocessor::RaptorQProcessor::new(
             self.settings.symbol_size,
-            self.settings.redundancy_factor);
+            self.settings.redundancy_factor)
+            .with_rq_ids_mode(self.rq_ids_mode(req.pastel_ids, req.sign_ids, req.initial_counter)?)
+            .with_rq_ids_compression(self.rq_ids_compression(req.ids_compression()))
+            .with_deterministic_rq_ids(req.deterministic_ids);
 
-        let req = request.into_inner();
-        match processor.create_metadata(&req.path, req.files_number,
-                                        &req.block_hash, &req.pastel_id) {
+        let options = rqprocessor::OutputOptions {
+            object_id: req.object_id.clone(),
+            output_path: req.output_path.clone(),
+            overwrite: req.overwrite,
+            ..Default::default() };
+        let result = self.workers.run(move || {
+            processor.create_metadata(&req.path, req.files_number,
+                                      &req.block_hash, &req.pastel_id, &options)
+        }).await?;
+        match result {
             Ok((meta, path)) => {
 
+                let (first_counter, last_counter) = meta.rq_ids_counters.unwrap_or_default();
                 let reply = rq::EncodeMetaDataReply {
                     encoder_parameters: meta.encoder_parameters,
                     symbols_count: meta.source_symbols+meta.repair_symbols,
-                    path };
+                    path,
+                    file_hash: meta.file_hash,
+                    rq_ids: meta.rq_ids,
+                    first_counter,
+                    last_counter };
 
                 Ok(Response::new(reply))
             },
-            Err(e) => {
-                log::error!("Internal error: {:?}", e);
-                Err(Status::internal("Internal error"))
-            }
+            Err(e) => Err(error_status(e))
         }
     }
 
@@ -55,22 +352,66 @@ impl RaptorQ for RaptorQService {
             self.settings.redundancy_factor);
 
         let req = request.into_inner();
-        match processor.encode(&req.path) {
+        let options = rqprocessor::OutputOptions {
+            object_id: req.object_id.clone(),
+            output_path: req.output_path.clone(),
+            overwrite: req.overwrite,
+            container: req.container };
+        let result = self.workers.run(move || processor.encode(&req.path, &options)).await?;
+        match result {
             Ok((meta, path)) => {
 
                 let reply = rq::EncodeReply {
                     encoder_parameters: meta.encoder_parameters,
                     symbols_count: meta.source_symbols+meta.repair_symbols,
-                    path };
+                    path,
+                    file_hash: meta.file_hash };
 
                 Ok(Response::new(reply))
             },
-            Err(e) => {
-                log::error!("Internal error: {:?}", e);
-                Err(Status::internal("Internal error"))
-            }
+            Err(e) => Err(error_status(e))
+        }
+    }
+    async fn encode_with_meta_data(&self, request: Request<EncodeWithMetaDataRequest>) -> Result<Response<EncodeWithMetaDataReply>, Status> {
+        log::info!("Got a 'encode_with_meta_data' request: {:?}", request);
+
+        let req = request.into_inner();
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor)
+            .with_rq_ids_mode(self.rq_ids_mode(req.pastel_ids, req.sign_ids, req.initial_counter)?)
+            .with_rq_ids_compression(self.rq_ids_compression(req.ids_compression()))
+            .with_deterministic_rq_ids(req.deterministic_ids);
+
+        let options = rqprocessor::OutputOptions {
+            object_id: req.object_id.clone(),
+            output_path: req.output_path.clone(),
+            overwrite: req.overwrite,
+            container: req.container };
+        let result = self.workers.run(move || {
+            processor.encode_with_metadata(&req.path, req.files_number,
+                                           &req.block_hash, &req.pastel_id, &options)
+        }).await?;
+        match result {
+            Ok((meta, symbols_path, meta_path)) => {
+
+                let (first_counter, last_counter) = meta.rq_ids_counters.unwrap_or_default();
+                let reply = rq::EncodeWithMetaDataReply {
+                    encoder_parameters: meta.encoder_parameters,
+                    symbols_count: meta.source_symbols+meta.repair_symbols,
+                    symbols_path,
+                    meta_path,
+                    file_hash: meta.file_hash,
+                    rq_ids: meta.rq_ids,
+                    first_counter,
+                    last_counter };
+
+                Ok(Response::new(reply))
+            },
+            Err(e) => Err(error_status(e))
         }
     }
+
     async fn decode(&self, request: Request<DecodeRequest>) -> Result<Response<DecodeReply>, Status> {
         log::info!("Got a 'decode' request: {:?}", request);
 
@@ -79,16 +420,671 @@ impl RaptorQ for RaptorQService {
             self.settings.redundancy_factor);
 
         let req = request.into_inner();
-        match processor.decode(&req.encoder_parameters, &req.path) {
-            Ok(path) => {
+        if !req.encoder_parameters.is_empty() {
+            rqprocessor::RaptorQProcessor::parse_encoder_parameters("decode", &req.encoder_parameters)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        }
+        let options = rqprocessor::OutputOptions {
+            output_path: req.output_path.clone(),
+            overwrite: req.overwrite,
+            ..Default::default() };
+        let result = self.workers.run(move || {
+            processor.decode(&req.encoder_parameters, &req.path, &req.metadata_path,
+                             &req.expected_hash, &options)
+        }).await?;
+        match result {
+            Ok((path, rejected_symbols)) => {
+
+                let reply = rq::DecodeReply { path, rejected_symbols };
+                Ok(Response::new(reply))
+            },
+            Err(e) => Err(error_status(e))
+        }
+    }
+
+    async fn repair_symbols(&self, request: Request<RepairSymbolsRequest>) -> Result<Response<RepairSymbolsReply>, Status> {
+        log::info!("Got a 'repair_symbols' request: {:?}", request);
+
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor)
+            .with_work_dir(&self.settings.work_dir);
+
+        let req = request.into_inner();
+        if !req.encoder_parameters.is_empty() {
+            rqprocessor::RaptorQProcessor::parse_encoder_parameters("repair_symbols", &req.encoder_parameters)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        }
+        let result = self.workers.run(move || {
+            processor.repair_symbols(&req.encoder_parameters, &req.path, &req.metadata_path)
+        }).await?;
+        match result {
+            Ok(regenerated_symbols) => {
+
+                let reply = rq::RepairSymbolsReply { regenerated_symbols };
+                Ok(Response::new(reply))
+            },
+            Err(e) => Err(error_status(e))
+        }
+    }
+
+    async fn verify_metadata(&self, request: Request<VerifyMetadataRequest>) -> Result<Response<VerifyMetadataReply>, Status> {
+        log::info!("Got a 'verify_metadata' request: {:?}", request);
+
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor);
+
+        let req = request.into_inner();
+        let result = self.workers.run(move || {
+            processor.verify_metadata(&req.path, &req.rq_ids_path, &req.block_hash, &req.pastel_id)
+        }).await?;
+        match result {
+            Ok(verification) => {
 
-                let reply = rq::DecodeReply { path };
+                let reply = rq::VerifyMetadataReply {
+                    matches: verification.symbols_match && verification.block_hash_matches &&
+                        verification.pastel_id_matches,
+                    symbols_match: verification.symbols_match,
+                    block_hash_matches: verification.block_hash_matches,
+                    pastel_id_matches: verification.pastel_id_matches,
+                    missing_symbols: verification.missing_symbols,
+                    unexpected_symbols: verification.unexpected_symbols };
                 Ok(Response::new(reply))
             },
-            Err(e) => {
-                log::error!("Internal error: {:?}", e);
-                Err(Status::internal("Internal error"))
+            Err(e) => Err(error_status(e))
+        }
+    }
+
+    async fn verify_ids_file(&self, request: Request<VerifyIdsFileRequest>) -> Result<Response<VerifyIdsFileReply>, Status> {
+        log::info!("Got a 'verify_ids_file' request: {:?}", request);
+
+        let req = request.into_inner();
+        if req.public_key.len() != 32 {
+            return Err(Status::invalid_argument(
+                format!("public_key must be 32 bytes long, got {}", req.public_key.len())));
+        }
+        let result = self.workers.run(move || {
+            rqprocessor::RaptorQProcessor::verify_ids_file(&req.rq_ids_path, &req.public_key)
+        }).await?;
+        match result {
+            Ok(verification) => {
+
+                let reply = rq::VerifyIdsFileReply {
+                    signed: verification.signed,
+                    signature_valid: verification.signature_valid,
+                    block_hash: verification.block_hash,
+                    pastel_id: verification.pastel_id,
+                    file_hash: verification.file_hash,
+                    symbols_count: verification.symbols_count,
+                    counter: verification.counter.unwrap_or_default() };
+                Ok(Response::new(reply))
+            },
+            Err(e) => Err(error_status(e))
+        }
+    }
+
+    async fn list_container_symbols(&self, request: Request<ListContainerSymbolsRequest>) -> Result<Response<ListContainerSymbolsReply>, Status> {
+        log::info!("Got a 'list_container_symbols' request: {:?}", request);
+
+        let req = request.into_inner();
+        let result = self.workers.run(move || {
+            rqprocessor::RaptorQProcessor::list_container(&req.path)
+        }).await?;
+        match result {
+            Ok(listing) => {
+
+                let symbols = listing.symbols.into_iter()
+                    .map(|entry| rq::ContainerSymbol {
+                        id: entry.id,
+                        offset: entry.offset,
+                        length: entry.length })
+                    .collect();
+                let reply = rq::ListContainerSymbolsReply {
+                    encoder_parameters: listing.encoder_parameters,
+                    file_hash: listing.file_hash,
+                    symbols };
+                Ok(Response::new(reply))
+            },
+            Err(e) => Err(error_status(e))
+        }
+    }
+
+    async fn extract_container_symbols(&self, request: Request<ExtractContainerSymbolsRequest>) -> Result<Response<ExtractContainerSymbolsReply>, Status> {
+        log::info!("Got a 'extract_container_symbols' request: {:?}", request);
+
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor);
+
+        let req = request.into_inner();
+        let result = self.workers.run(move || {
+            processor.extract_symbols(&req.path, &req.ids, &req.output_path, req.overwrite)
+        }).await?;
+        match result {
+            Ok((path, ids)) => {
+
+                let reply = rq::ExtractContainerSymbolsReply {
+                    path,
+                    ids };
+                Ok(Response::new(reply))
+            },
+            Err(e) => Err(error_status(e))
+        }
+    }
+
+    async fn check_symbols(&self, request: Request<CheckSymbolsRequest>) -> Result<Response<CheckSymbolsReply>, Status> {
+        log::info!("Got a 'check_symbols' request: {:?}", request);
+
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor);
+
+        let req = request.into_inner();
+        if !req.encoder_parameters.is_empty() {
+            rqprocessor::RaptorQProcessor::parse_encoder_parameters("check_symbols", &req.encoder_parameters)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        }
+        let result = self.workers.run(move || {
+            processor.check_symbols(&req.encoder_parameters, &req.path, &req.metadata_path)
+        }).await?;
+        match result {
+            Ok(check) => {
+
+                let reply = rq::CheckSymbolsReply {
+                    valid_symbols: check.valid,
+                    missing_symbols: check.missing,
+                    corrupted_symbols: check.corrupted,
+                    unlisted_symbols: check.unlisted,
+                    decodable: check.symbols_needed == 0,
+                    symbols_needed: check.symbols_needed };
+                Ok(Response::new(reply))
+            },
+            Err(e) => Err(error_status(e))
+        }
+    }
+
+    type EncodeWithProgressStream = ReceiverStream<Result<EncodeProgressReply, Status>>;
+
+    async fn encode_with_progress(&self, request: Request<EncodeRequest>) -> Result<Response<Self::EncodeWithProgressStream>, Status> {
+        log::info!("Got a 'encode_with_progress' request: {:?}", request);
+
+        let (tx, rx) = mpsc::channel(PROGRESS_CHANNEL_SIZE);
+        let processor = streaming_processor(&self.settings, tx.clone(), |progress| {
+            EncodeProgressReply { event: Some(rq::encode_progress_reply::Event::Progress(progress)) }
+        });
+
+        let req = request.into_inner();
+        let options = rqprocessor::OutputOptions {
+            object_id: req.object_id.clone(),
+            output_path: req.output_path.clone(),
+            overwrite: req.overwrite,
+            container: req.container };
+        let admission = self.workers.admit()?;
+        tokio::spawn(async move {
+            let result = admission.run(move || processor.encode(&req.path, &options)).await;
+            let reply = match result {
+                Ok(Ok((meta, path))) => {
+                    let reply = rq::EncodeReply {
+                        encoder_parameters: meta.encoder_parameters,
+                        symbols_count: meta.source_symbols+meta.repair_symbols,
+                        path,
+                        file_hash: meta.file_hash };
+                    Ok(EncodeProgressReply { event: Some(rq::encode_progress_reply::Event::Result(reply)) })
+                },
+                Ok(Err(e)) => Err(error_status(e)),
+                Err(status) => Err(status)
+            };
+            let _ = tx.send(reply).await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type DecodeWithProgressStream = ReceiverStream<Result<DecodeProgressReply, Status>>;
+
+    async fn decode_with_progress(&self, request: Request<DecodeRequest>) -> Result<Response<Self::DecodeWithProgressStream>, Status> {
+        log::info!("Got a 'decode_with_progress' request: {:?}", request);
+
+        let req = request.into_inner();
+        if !req.encoder_parameters.is_empty() {
+            rqprocessor::RaptorQProcessor::parse_encoder_parameters("decode_with_progress", &req.encoder_parameters)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        }
+
+        let (tx, rx) = mpsc::channel(PROGRESS_CHANNEL_SIZE);
+        let processor = streaming_processor(&self.settings, tx.clone(), |progress| {
+            DecodeProgressReply { event: Some(rq::decode_progress_reply::Event::Progress(progress)) }
+        });
+
+        let options = rqprocessor::OutputOptions {
+            output_path: req.output_path.clone(),
+            overwrite: req.overwrite,
+            ..Default::default() };
+        let admission = self.workers.admit()?;
+        tokio::spawn(async move {
+            let result = admission.run(move || {
+                processor.decode(&req.encoder_parameters, &req.path, &req.metadata_path,
+                                 &req.expected_hash, &options)
+            }).await;
+            let reply = match result {
+                Ok(Ok((path, rejected_symbols))) => {
+                    let reply = rq::DecodeReply { path, rejected_symbols };
+                    Ok(DecodeProgressReply { event: Some(rq::decode_progress_reply::Event::Result(reply)) })
+                },
+                Ok(Err(e)) => Err(error_status(e)),
+                Err(status) => Err(status)
+            };
+            let _ = tx.send(reply).await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn encode_stream(&self, request: Request<Streaming<EncodeStreamRequest>>) -> Result<Response<EncodeStreamReply>, Status> {
+        log::info!("Got a 'encode_stream' request: {:?}", request.metadata());
+
+        let mut stream = request.into_inner();
+        let header = match stream.message().await? {
+            Some(EncodeStreamRequest { data: Some(rq::encode_stream_request::Data::Header(header)) }) => header,
+            _ => return Err(Status::invalid_argument("The first message must be the encoding header"))
+        };
+        let mode = header.mode();
+        if header.return_symbols && mode != rq::EncodeStreamMode::Symbols {
+            return Err(Status::invalid_argument("Symbols can only be returned in the SYMBOLS mode"));
+        }
+
+        let rq_ids_mode = self.rq_ids_mode(header.pastel_ids, header.sign_ids, header.initial_counter)?;
+        let upload = receive_upload(&self.settings.work_dir, &mut stream).await?;
+
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor)
+            .with_rq_ids_mode(rq_ids_mode)
+            .with_rq_ids_compression(self.rq_ids_compression(header.ids_compression()))
+            .with_deterministic_rq_ids(header.deterministic_ids)
+            // The upload is stored under a random name, only the client knows the real one
+            .with_rq_ids_file_name(std::path::Path::new(&header.file_name).file_name()
+                .map(|name| name.to_string_lossy().to_string()));
+
+        // The uploaded file lives in the work directory, so the results go there too by default
+        let options = rqprocessor::OutputOptions {
+            object_id: header.object_id.clone(),
+            output_path: if header.output_path.is_empty() {
+                self.settings.work_dir.clone()
+            } else {
+                header.output_path.clone()
+            },
+            overwrite: header.overwrite,
+            container: header.container };
+        let result = self.workers.run(move || -> Result<EncodeStreamReply, rqprocessor::RqProcessorError> {
+            let input = upload.path_string();
+            let return_symbols = header.return_symbols &&
+                processor.encoded_size(&input)? <= MAX_RETURNED_SYMBOLS_SIZE;
+            match mode {
+                rq::EncodeStreamMode::Symbols if return_symbols => {
+                    let mut symbols = Vec::new();
+                    let meta = processor.encode_each_symbol(&input, |id, _payload_id, data| {
+                        symbols.push(rq::Symbol { id: id.to_string(), data });
+                        Ok(())
+                    })?;
+                    Ok(encode_stream_reply(meta, String::new(), String::new(), symbols))
+                },
+                rq::EncodeStreamMode::Symbols => {
+                    let (meta, symbols_path) = processor.encode(&input, &options)?;
+                    Ok(encode_stream_reply(meta, symbols_path, String::new(), Vec::new()))
+                },
+                rq::EncodeStreamMode::Metadata => {
+                    let (meta, meta_path) = processor.create_metadata(&input, header.files_number,
+                                                                      &header.block_hash, &header.pastel_id,
+                                                                      &options)?;
+                    Ok(encode_stream_reply(meta, String::new(), meta_path, Vec::new()))
+                },
+                rq::EncodeStreamMode::SymbolsAndMetadata => {
+                    let (meta, symbols_path, meta_path) =
+                        processor.encode_with_metadata(&input, header.files_number,
+                                                       &header.block_hash, &header.pastel_id, &options)?;
+                    Ok(encode_stream_reply(meta, symbols_path, meta_path, Vec::new()))
+                }
             }
+        }).await?;
+        match result {
+            Ok(reply) => Ok(Response::new(reply)),
+            Err(e) => Err(error_status(e))
+        }
+    }
+
+    type StreamSymbolsStream = ReceiverStream<Result<StreamSymbolsReply, Status>>;
+
+    async fn stream_symbols(&self, request: Request<StreamSymbolsRequest>) -> Result<Response<Self::StreamSymbolsStream>, Status> {
+        log::info!("Got a 'stream_symbols' request: {:?}", request);
+
+        let req = request.into_inner();
+        if req.path.is_empty() == req.symbols_path.is_empty() {
+            return Err(Status::invalid_argument("Exactly one of path and symbols_path must be set"));
+        }
+
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor);
+
+        let (tx, rx) = mpsc::channel(PROGRESS_CHANNEL_SIZE);
+        let admission = self.workers.admit()?;
+        tokio::spawn(async move {
+            let symbols_tx = tx.clone();
+            let result = admission.run(move || -> Result<rq::StreamSymbolsSummary, rqprocessor::RqProcessorError> {
+                let mut symbols_count = 0u32;
+                let mut send = |id: &str, payload_id, data| {
+                    symbols_count += 1;
+                    match symbols_tx.blocking_send(Ok(symbol_reply(id, payload_id, data))) {
+                        Ok(_) => Ok(()),
+                        Err(_) => Err(rqprocessor::RqProcessorError::new("stream_symbols",
+                                                                         "Client has gone away",
+                                                                         "".to_string()))
+                    }
+                };
+
+                if !req.path.is_empty() {
+                    let meta = processor.encode_each_symbol(&req.path, &mut send)?;
+                    Ok(rq::StreamSymbolsSummary {
+                        encoder_parameters: meta.encoder_parameters,
+                        symbols_count,
+                        file_hash: meta.file_hash,
+                        rejected_symbols: Vec::new() })
+                } else {
+                    let (manifest, rejected_symbols) = processor.read_each_symbol(&req.symbols_path, &mut send)?;
+                    let (encoder_parameters, file_hash) = match manifest {
+                        Some(manifest) => (manifest.encoder_parameters, manifest.file_hash),
+                        None => (Vec::new(), String::new())
+                    };
+                    Ok(rq::StreamSymbolsSummary {
+                        encoder_parameters,
+                        symbols_count,
+                        file_hash,
+                        rejected_symbols })
+                }
+            }).await;
+            let reply = match result {
+                Ok(Ok(summary)) => Ok(StreamSymbolsReply { event: Some(rq::stream_symbols_reply::Event::Summary(summary)) }),
+                Ok(Err(e)) => Err(error_status(e)),
+                Err(status) => Err(status)
+            };
+            let _ = tx.send(reply).await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type DecodeStreamStream = ReceiverStream<Result<DecodeStreamReply, Status>>;
+
+    async fn decode_stream(&self, request: Request<Streaming<DecodeStreamRequest>>) -> Result<Response<Self::DecodeStreamStream>, Status> {
+        log::info!("Got a 'decode_stream' request: {:?}", request.metadata());
+
+        let mut stream = request.into_inner();
+        let header = match stream.message().await? {
+            Some(DecodeStreamRequest { data: Some(rq::decode_stream_request::Data::Header(header)) }) => header,
+            _ => return Err(Status::invalid_argument("The first message must be the decoding header"))
+        };
+        if header.encoder_parameters.is_empty() {
+            return Err(Status::invalid_argument("encoder_parameters must be set"));
+        }
+        rqprocessor::RaptorQProcessor::parse_encoder_parameters("decode_stream", &header.encoder_parameters)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        // Without an output path the file is restored into the work directory,
+        // and only kept there when it is not sent back
+        let temporary = header.output_path.is_empty();
+        let options = rqprocessor::OutputOptions {
+            output_path: if temporary {
+                std::path::Path::new(&self.settings.work_dir).join("restored")
+                    .join(uuid::Uuid::new_v4().to_string()).to_string_lossy().to_string()
+            } else {
+                header.output_path.clone()
+            },
+            overwrite: header.overwrite,
+            ..Default::default() };
+
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor);
+        let encoder_parameters = header.encoder_parameters.clone();
+        let expected_hash = header.expected_hash.clone();
+        let decoder = self.workers.run(move || {
+            processor.packets_decoder(&encoder_parameters, &expected_hash, &options)
+        }).await?.map_err(error_status)?;
+
+        let (packets_tx, mut packets_rx) = mpsc::channel(PROGRESS_CHANNEL_SIZE);
+        tokio::spawn(async move {
+            while let Ok(Some(message)) = stream.message().await {
+                match message.data {
+                    Some(rq::decode_stream_request::Data::Symbol(data)) => {
+                        if packets_tx.send(data).await.is_err() {
+                            break;
+                        }
+                    },
+                    _ => log::warn!("Ignoring a 'decode_stream' message without a symbol")
+                }
+            }
+        });
+
+        let workers = self.workers.clone();
+        let (tx, rx) = mpsc::channel(PROGRESS_CHANNEL_SIZE);
+        tokio::spawn(async move {
+            let result = decode_received_packets(&workers, decoder, &mut packets_rx).await;
+            // Tell the client right away, so it can stop fetching symbols
+            drop(packets_rx);
+            let (path, symbols_received, symbols_rejected) = match result {
+                Ok(restored) => restored,
+                Err(status) => {
+                    let _ = tx.send(Err(status)).await;
+                    return;
+                }
+            };
+
+            let restored = rq::DecodeStreamRestored {
+                path: if header.return_data && temporary { String::new() } else { path.clone() },
+                symbols_received,
+                symbols_rejected };
+            let reply = DecodeStreamReply { event: Some(rq::decode_stream_reply::Event::Restored(restored)) };
+            if tx.send(Ok(reply)).await.is_err() || !header.return_data {
+                return;
+            }
+
+            // Sending the file back only waits for the client, so it doesn't need a worker slot
+            let data_tx = tx.clone();
+            let sent = tokio::task::spawn_blocking(move || send_restored_file(&path, temporary, &data_tx)).await;
+            let error = match sent {
+                Ok(Ok(())) => return,
+                Ok(Err(e)) => error_status(e),
+                Err(err) => {
+                    log::error!("Sending the restored file failed: {:?}", err);
+                    Status::internal("Internal error")
+                }
+            };
+            let _ = tx.send(Err(error)).await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn open_decode_session(&self, request: Request<OpenDecodeSessionRequest>) -> Result<Response<SessionStatusReply>, Status> {
+        log::info!("Got a 'open_decode_session' request: {:?}", request);
+
+        let req = request.into_inner();
+        if req.encoder_parameters.is_empty() {
+            return Err(Status::invalid_argument("encoder_parameters must be set"));
+        }
+        rqprocessor::RaptorQProcessor::parse_encoder_parameters("open_decode_session", &req.encoder_parameters)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let output_path = if req.output_path.is_empty() {
+            std::path::Path::new(&self.settings.work_dir).join("restored")
+                .join(&session_id).to_string_lossy().to_string()
+        } else {
+            req.output_path.clone()
+        };
+        let result = self.workers.run(move || {
+            DecodeSession::open(&req.encoder_parameters, &output_path, &req.expected_hash, req.overwrite)
+        }).await?;
+        match result {
+            Ok(session) => {
+                let status = session.status(self.sessions.timeout());
+                self.sessions.insert(&session_id, session)?;
+                Ok(Response::new(session_status_reply(session_id, status)))
+            },
+            Err(e) => Err(error_status(e))
+        }
+    }
+
+    async fn add_symbols(&self, request: Request<AddSymbolsRequest>) -> Result<Response<SessionStatusReply>, Status> {
+        log::info!("Got a 'add_symbols' request: {} symbols, paths {:?}",
+                   request.get_ref().symbols.len(), request.get_ref().paths);
+
+        let req = request.into_inner();
+        let session = match self.sessions.get(&req.session_id) {
+            Some(session) => session,
+            None => return Err(Status::not_found(format!("Unknown session {}", req.session_id)))
+        };
+
+        let session_id = req.session_id.clone();
+        let timeout = self.sessions.timeout();
+        let result = self.workers.run(move || -> Option<Result<SessionStatus, rqprocessor::RqProcessorError>> {
+            let mut session = session.lock().ok()?;
+            Some(session.add_symbols(&req.symbols, &req.paths).map(|_| session.status(timeout)))
+        }).await?;
+        match result {
+            Some(Ok(status)) => Ok(Response::new(session_status_reply(session_id, status))),
+            Some(Err(e)) => Err(error_status(e)),
+            None => Err(self.drop_poisoned_session(&session_id))
+        }
+    }
+
+    async fn session_status(&self, request: Request<SessionRequest>) -> Result<Response<SessionStatusReply>, Status> {
+        let req = request.into_inner();
+        let session = match self.sessions.get(&req.session_id) {
+            Some(session) => session,
+            None => return Err(Status::not_found(format!("Unknown session {}", req.session_id)))
+        };
+        // Never wait for a long running 'add_symbols' on the async workers
+        let status = match session.try_lock() {
+            Ok(session) => session.status(self.sessions.timeout()),
+            Err(TryLockError::WouldBlock) => {
+                return Err(Status::unavailable("Session is busy adding symbols, try again later"));
+            },
+            Err(TryLockError::Poisoned(_)) => return Err(self.drop_poisoned_session(&req.session_id))
+        };
+        Ok(Response::new(session_status_reply(req.session_id, status)))
+    }
+
+    async fn close_session(&self, request: Request<SessionRequest>) -> Result<Response<SessionStatusReply>, Status> {
+        log::info!("Got a 'close_session' request: {:?}", request);
+
+        let req = request.into_inner();
+        let session = match self.sessions.remove(&req.session_id) {
+            Some(session) => session,
+            None => return Err(Status::not_found(format!("Unknown session {}", req.session_id)))
+        };
+        // Waits for a running 'add_symbols' to finish, off the async workers
+        let timeout = self.sessions.timeout();
+        let status = tokio::task::spawn_blocking(move || {
+            session.lock().map(|session| session.status(timeout)).ok()
+        }).await;
+        match status {
+            Ok(Some(status)) => Ok(Response::new(session_status_reply(req.session_id, status))),
+            _ => Err(Status::internal("Internal error"))
+        }
+    }
+
+    async fn submit_encode(&self, request: Request<EncodeRequest>) -> Result<Response<JobReply>, Status> {
+        log::info!("Got a 'submit_encode' request: {:?}", request);
+
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor);
+
+        let req = request.into_inner();
+        let options = rqprocessor::OutputOptions {
+            object_id: req.object_id.clone(),
+            output_path: req.output_path.clone(),
+            overwrite: req.overwrite,
+            container: req.container };
+        let job_id = self.jobs.submit("encode", move |control| {
+            let (meta, path) = processor.with_control(control).encode(&req.path, &options)?;
+            Ok(encoder_job_result(meta, vec![path]))
+        })?;
+
+        Ok(Response::new(rq::JobReply { job_id }))
+    }
+
+    async fn submit_metadata(&self, request: Request<EncodeMetaDataRequest>) -> Result<Response<JobReply>, Status> {
+        log::info!("Got a 'submit_metadata' request: {:?}", request);
+
+        let req = request.into_inner();
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor)
+            .with_rq_ids_mode(self.rq_ids_mode(req.pastel_ids, req.sign_ids, req.initial_counter)?)
+            .with_rq_ids_compression(self.rq_ids_compression(req.ids_compression()))
+            .with_deterministic_rq_ids(req.deterministic_ids);
+
+        let options = rqprocessor::OutputOptions {
+            object_id: req.object_id.clone(),
+            output_path: req.output_path.clone(),
+            overwrite: req.overwrite,
+            ..Default::default() };
+        let job_id = self.jobs.submit("metadata", move |control| {
+            let (meta, path) = processor.with_control(control)
+                .create_metadata(&req.path, req.files_number, &req.block_hash, &req.pastel_id, &options)?;
+            Ok(encoder_job_result(meta, vec![path]))
+        })?;
+
+        Ok(Response::new(rq::JobReply { job_id }))
+    }
+
+    async fn submit_decode(&self, request: Request<DecodeRequest>) -> Result<Response<JobReply>, Status> {
+        log::info!("Got a 'submit_decode' request: {:?}", request);
+
+        let processor = rqprocessor::RaptorQProcessor::new(
+            self.settings.symbol_size,
+            self.settings.redundancy_factor);
+
+        let req = request.into_inner();
+        if !req.encoder_parameters.is_empty() {
+            rqprocessor::RaptorQProcessor::parse_encoder_parameters("submit_decode", &req.encoder_parameters)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        }
+        let options = rqprocessor::OutputOptions {
+            output_path: req.output_path.clone(),
+            overwrite: req.overwrite,
+            ..Default::default() };
+        let job_id = self.jobs.submit("decode", move |control| {
+            let (path, rejected_symbols) = processor.with_control(control)
+                .decode(&req.encoder_parameters, &req.path, &req.metadata_path, &req.expected_hash, &options)?;
+            Ok(JobResult {
+                paths: vec![path],
+                rejected_symbols,
+                ..Default::default() })
+        })?;
+
+        Ok(Response::new(rq::JobReply { job_id }))
+    }
+
+    async fn get_job_status(&self, request: Request<JobRequest>) -> Result<Response<JobStatusReply>, Status> {
+        let req = request.into_inner();
+        match self.jobs.status(&req.job_id) {
+            Some(status) => Ok(Response::new(job_status_reply(req.job_id, status))),
+            None => Err(Status::not_found(format!("Unknown job {}", req.job_id)))
+        }
+    }
+
+    async fn cancel_job(&self, request: Request<JobRequest>) -> Result<Response<JobStatusReply>, Status> {
+        log::info!("Got a 'cancel_job' request: {:?}", request);
+
+        let req = request.into_inner();
+        match self.jobs.cancel(&req.job_id) {
+            Some(status) => Ok(Response::new(job_status_reply(req.job_id, status))),
+            None => Err(Status::not_found(format!("Unknown job {}", req.job_id)))
         }
     }
 }
@@ -99,7 +1095,7 @@ pub async fn start_server(settings: &ServiceSettings) -> Result<(), Box<dyn std:
 
     log::info!("RaptorQ gRPC Server listening on {}", addr);
 
-    let raptorq_service = RaptorQService{settings: settings.clone()};
+    let raptorq_service = RaptorQService::new(settings)?;
     let srv = RaptorQServer::new(raptorq_service);
 
     Server::builder().add_service(srv).serve(addr).await?;
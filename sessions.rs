@@ -0,0 +1,356 @@
+// Copyright (c) 2021-2021 The Pastel Core developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::{Duration, Instant};
+use tonic::Status;
+
+use crate::decoder::{PacketResult, StreamDecoder};
+use crate::rqprocessor::{RaptorQProcessor, RqProcessorError};
+
+#[derive(Debug, Clone)]
+pub struct SessionStatus {
+    pub symbols_received: u32,
+    pub symbols_rejected: u32,
+    pub symbols_needed: u64,
+    /// Empty until the file is restored
+    pub path: String,
+    pub error: String,
+    pub expires_in: Duration
+}
+
+/// Restores a file from symbols added over any number of calls.
+/// The output is written to a `.partial` file, which is removed
+/// when the session is dropped before the file is restored.
+pub struct DecodeSession {
+    decoder: Option<StreamDecoder<File>>,
+    symbols_needed: u64,
+    partial_file: PathBuf,
+    rest_file: PathBuf,
+    expected_hash: String,
+    received: u32,
+    rejected: u32,
+    path: String,
+    error: String,
+    last_used: Instant
+}
+
+impl DecodeSession {
+
+    pub fn open(encoder_parameters: &Vec<u8>, output_path: &str, expected_hash: &str, overwrite: bool)
+        -> Result<Self, RqProcessorError> {
+
+        let config = RaptorQProcessor::parse_encoder_parameters("open_session", encoder_parameters)?;
+        let rest_file = PathBuf::from(output_path);
+        let (partial_file, output_file) =
+            RaptorQProcessor::create_partial_output("open_session", &rest_file, overwrite)?;
+
+        let decoder = StreamDecoder::new(output_file, config);
+        Ok(DecodeSession {
+            symbols_needed: decoder.symbols_needed(),
+            decoder: Some(decoder),
+            partial_file,
+            rest_file,
+            expected_hash: expected_hash.to_string(),
+            received: 0,
+            rejected: 0,
+            path: String::new(),
+            error: String::new(),
+            last_used: Instant::now()
+        })
+    }
+
+    /// Adds a serialized packet. Packets added after the file is restored are ignored.
+    pub fn add_packet(&mut self, data: &[u8]) -> Result<(), RqProcessorError> {
+
+        self.last_used = Instant::now();
+        let decoder = match self.decoder.as_mut() {
+            Some(decoder) => decoder,
+            None => return Ok(())
+        };
+
+        self.received += 1;
+        match decoder.add_packet(data) {
+            Ok(PacketResult::Rejected) => self.rejected += 1,
+            Ok(_) => {},
+            Err(err) => {
+                return Err(RqProcessorError::new_file_err("add_symbols",
+                                                          "Cannot write into the file",
+                                                          self.partial_file.as_path(),
+                                                          err.to_string()));
+            }
+        }
+        self.symbols_needed = decoder.symbols_needed();
+
+        if decoder.is_restored() {
+            self.finish();
+        }
+        Ok(())
+    }
+
+    /// Adds serialized packets, then symbol files or directories of them
+    pub fn add_symbols(&mut self, symbols: &[Vec<u8>], paths: &[String]) -> Result<(), RqProcessorError> {
+        for data in symbols {
+            self.add_packet(data)?;
+        }
+        for path in paths {
+            self.add_path(path)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a symbol file, or all symbol files in a directory
+    pub fn add_path(&mut self, path: &str) -> Result<(), RqProcessorError> {
+
+        let path = Path::new(path);
+        if !path.is_dir() {
+            return self.add_file(path);
+        }
+
+        let symbol_files = match fs::read_dir(path) {
+            Ok(paths) => paths,
+            Err(err) => {
+                return Err(RqProcessorError::new_file_err("add_symbols",
+                                                          "Cannot get list of input files",
+                                                          path,
+                                                          err.to_string()));
+            }
+        };
+        for symbol_file in symbol_files {
+            let file_path = match symbol_file {
+                Ok(entry) => entry.path(),
+                Err(err) => {
+                    return Err(RqProcessorError::new_file_err("add_symbols",
+                                                              "Cannot get file path",
+                                                              path,
+                                                              err.to_string()));
+                }
+            };
+            if file_path.is_file() {
+                self.add_file(&file_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn status(&self, timeout: Duration) -> SessionStatus {
+        SessionStatus {
+            symbols_received: self.received,
+            symbols_rejected: self.rejected,
+            symbols_needed: self.symbols_needed,
+            path: self.path.clone(),
+            error: self.error.clone(),
+            expires_in: timeout.checked_sub(self.last_used.elapsed()).unwrap_or_default()
+        }
+    }
+
+    fn add_file(&mut self, file_path: &Path) -> Result<(), RqProcessorError> {
+        match fs::read(file_path) {
+            Ok(data) => self.add_packet(&data),
+            Err(err) => {
+                log::warn!("Skipping symbol file {:?} - {}", file_path, err);
+                self.received += 1;
+                self.rejected += 1;
+                Ok(())
+            }
+        }
+    }
+
+    // Blocks may be restored in any order, so the file is hashed once it is complete
+    fn finish(&mut self) {
+        self.decoder = None;
+
+        let result = RaptorQProcessor::file_hash("add_symbols", &self.partial_file)
+            .and_then(|restored_hash| {
+                RaptorQProcessor::move_into_place("add_symbols", &self.partial_file, &self.rest_file,
+                                                  &restored_hash, &self.expected_hash)
+            });
+        match result {
+            Ok(_) => self.path = self.rest_file.to_string_lossy().to_string(),
+            Err(err) => {
+                log::error!("Cannot restore file in a decode session: {:?}", err);
+                self.error = err.to_string();
+            }
+        }
+    }
+}
+
+impl Drop for DecodeSession {
+    fn drop(&mut self) {
+        if self.path.is_empty() {
+            self.decoder = None;
+            let _ = fs::remove_file(&self.partial_file);
+        }
+    }
+}
+
+impl fmt::Debug for DecodeSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DecodeSession")
+            .field("rest_file", &self.rest_file)
+            .field("received", &self.received)
+            .field("rejected", &self.rejected)
+            .field("symbols_needed", &self.symbols_needed)
+            .finish()
+    }
+}
+
+/// Keeps decode sessions alive between requests, at most `max_sessions` at a time.
+/// Sessions not used for `timeout` are dropped together with their partial output.
+#[derive(Debug, Clone)]
+pub struct SessionManager {
+    sessions: Arc<Mutex<HashMap<String, Arc<Mutex<DecodeSession>>>>>,
+    timeout: Duration,
+    max_sessions: usize
+}
+
+impl SessionManager {
+
+    pub fn new(timeout: Duration, max_sessions: usize) -> Self {
+
+        SessionManager {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            timeout,
+            max_sessions
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Fails with `RESOURCE_EXHAUSTED` when `max_sessions` are open,
+    /// the session is dropped together with its partial output then
+    pub fn insert(&self, session_id: &str, session: DecodeSession) -> Result<(), Status> {
+        self.purge_expired();
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.len() >= self.max_sessions {
+            log::warn!("{} decode sessions are open, rejecting a new one", sessions.len());
+            return Err(Status::resource_exhausted("Too many decode sessions are open, try again later"));
+        }
+        sessions.insert(session_id.to_string(), Arc::new(Mutex::new(session)));
+        Ok(())
+    }
+
+    pub fn get(&self, session_id: &str) -> Option<Arc<Mutex<DecodeSession>>> {
+        self.purge_expired();
+        self.sessions.lock().unwrap().get(session_id).cloned()
+    }
+
+    pub fn remove(&self, session_id: &str) -> Option<Arc<Mutex<DecodeSession>>> {
+        self.purge_expired();
+        self.sessions.lock().unwrap().remove(session_id)
+    }
+
+    // Sessions busy with adding symbols are never expired, poisoned ones are dropped
+    fn purge_expired(&self) {
+        let timeout = self.timeout;
+        self.sessions.lock().unwrap().retain(|_, session| match session.try_lock() {
+            Ok(session) => session.last_used.elapsed() < timeout,
+            Err(TryLockError::WouldBlock) => true,
+            Err(TryLockError::Poisoned(_)) => false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_packets() -> (Vec<u8>, String, Vec<Vec<u8>>) {
+        let processor = RaptorQProcessor::new(
+            50_000,
+            12);
+
+        let mut packets = Vec::new();
+        let meta = processor.encode_each_symbol(&String::from("test/10_000_000"), |_id, _payload_id, data| {
+            packets.push(data);
+            Ok(())
+        }).unwrap();
+        // Repair symbols first
+        packets.reverse();
+        (meta.encoder_parameters, meta.file_hash, packets)
+    }
+
+    #[test]
+    fn restore_over_several_calls() {
+        let (encoder_parameters, file_hash, packets) = encode_packets();
+        let sessions = SessionManager::new(Duration::from_secs(60), 4);
+        let session = DecodeSession::open(&encoder_parameters, "test/session_restored", &file_hash, true).unwrap();
+        sessions.insert("restore", session).unwrap();
+
+        let session = sessions.get("restore").unwrap();
+        for packet in &packets[..100] {
+            session.lock().unwrap().add_packet(packet).unwrap();
+        }
+        session.lock().unwrap().add_packet(b"not a symbol").unwrap();
+        let status = session.lock().unwrap().status(sessions.timeout());
+        assert_eq!(status.symbols_received, 101);
+        assert_eq!(status.symbols_rejected, 1);
+        assert_eq!(status.symbols_needed, 102);
+        assert!(status.path.is_empty());
+
+        let session = sessions.get("restore").unwrap();
+        for packet in &packets[100..] {
+            session.lock().unwrap().add_packet(packet).unwrap();
+        }
+        let status = session.lock().unwrap().status(sessions.timeout());
+        assert_eq!(status.symbols_needed, 0);
+        assert_eq!(status.path, "test/session_restored");
+        assert_eq!(RaptorQProcessor::file_hash("test", Path::new(&status.path)).unwrap(), file_hash);
+    }
+
+    #[test]
+    fn expired_sessions_are_removed() {
+        let (encoder_parameters, file_hash, packets) = encode_packets();
+        let sessions = SessionManager::new(Duration::from_secs(0), 4);
+        let mut session = DecodeSession::open(&encoder_parameters, "test/session_expired", &file_hash, true).unwrap();
+        session.add_packet(&packets[0]).unwrap();
+        sessions.insert("expired", session).unwrap();
+
+        assert!(sessions.get("expired").is_none());
+        assert!(!Path::new("test/session_expired.partial").exists());
+    }
+
+    #[test]
+    fn too_many_sessions() {
+        let (encoder_parameters, file_hash, packets) = encode_packets();
+        let sessions = SessionManager::new(Duration::from_secs(60), 1);
+        let session = DecodeSession::open(&encoder_parameters, "test/session_first", &file_hash, true).unwrap();
+        sessions.insert("first", session).unwrap();
+
+        let mut session = DecodeSession::open(&encoder_parameters, "test/session_second", &file_hash, true).unwrap();
+        session.add_packet(&packets[0]).unwrap();
+        let status = sessions.insert("second", session).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(sessions.get("second").is_none());
+        assert!(!Path::new("test/session_second.partial").exists());
+
+        // Closing a session makes room for another one
+        sessions.remove("first").unwrap();
+        let session = DecodeSession::open(&encoder_parameters, "test/session_second", &file_hash, true).unwrap();
+        sessions.insert("second", session).unwrap();
+    }
+
+    #[test]
+    fn poisoned_sessions_are_removed() {
+        let (encoder_parameters, file_hash, _packets) = encode_packets();
+        let sessions = SessionManager::new(Duration::from_secs(60), 4);
+        let session = DecodeSession::open(&encoder_parameters, "test/session_poisoned", &file_hash, true).unwrap();
+        sessions.insert("poisoned", session).unwrap();
+
+        let session = sessions.get("poisoned").unwrap();
+        let _ = std::thread::spawn(move || {
+            let _session = session.lock().unwrap();
+            panic!("panic while adding symbols");
+        }).join();
+
+        assert!(sessions.get("poisoned").is_none());
+        assert!(!Path::new("test/session_poisoned.partial").exists());
+    }
+}
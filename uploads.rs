@@ -0,0 +1,70 @@
+// Copyright (c) 2021-2021 The Pastel Core developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+const UPLOADS_DIR: &str = "uploads";
+
+/// A file received from a client over gRPC, stored under `<work_dir>/uploads`.
+/// The file is removed once it is dropped, so it only lives as long as the request using it.
+#[derive(Debug)]
+pub struct UploadedFile {
+    path: PathBuf
+}
+
+impl UploadedFile {
+
+    pub async fn create(work_dir: &str) -> io::Result<(UploadedFile, tokio::fs::File)> {
+
+        let dir = Path::new(work_dir).join(UPLOADS_DIR);
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let path = dir.join(Uuid::new_v4().to_string());
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path).await?;
+
+        Ok((UploadedFile { path }, file))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn path_string(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            log::warn!("Cannot remove uploaded file {:?} - {}", self.path, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn removed_on_drop() {
+        let (upload, mut file) = UploadedFile::create("test/work").await.unwrap();
+        file.write_all(b"uploaded").await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+
+        let path = upload.path().to_path_buf();
+        assert_eq!(fs::read(&path).unwrap(), b"uploaded");
+
+        drop(upload);
+        assert!(!path.exists());
+    }
+}
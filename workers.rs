@@ -0,0 +1,111 @@
+// Copyright (c) 2021-2021 The Pastel Core developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or http://www.opensource.org/licenses/mit-license.php.
+
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::Status;
+
+/// Runs CPU-heavy RaptorQ jobs on tokio's blocking threads instead of the async workers.
+/// At most `max_jobs` jobs run at a time, up to `max_queued_jobs` more wait for a free slot
+/// and anything above that is rejected with `RESOURCE_EXHAUSTED`.
+#[derive(Debug, Clone)]
+pub struct WorkerPool {
+    running: Arc<Semaphore>,
+    admitted: Arc<Semaphore>
+}
+
+impl WorkerPool {
+
+    pub fn new(max_jobs: usize, max_queued_jobs: usize) -> Self {
+
+        let max_jobs = std::cmp::max(max_jobs, 1);
+        WorkerPool {
+            running: Arc::new(Semaphore::new(max_jobs)),
+            admitted: Arc::new(Semaphore::new(max_jobs + max_queued_jobs))
+        }
+    }
+
+    pub async fn run<F, T>(&self, f: F) -> Result<T, Status>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static {
+
+        self.admit()?.run(f).await
+    }
+
+    /// Takes a place in the queue, so a job can be accepted or rejected
+    /// before it is started in the background.
+    pub fn admit(&self) -> Result<Admission, Status> {
+
+        match self.admitted.clone().try_acquire_owned() {
+            Ok(permit) => Ok(Admission { running: self.running.clone(), admitted: permit }),
+            Err(_) => {
+                log::warn!("Worker pool is full, rejecting the request");
+                Err(Status::resource_exhausted("Too many requests are being processed, try again later"))
+            }
+        }
+    }
+}
+
+/// A job accepted by the `WorkerPool`, waiting for a free slot
+#[derive(Debug)]
+pub struct Admission {
+    running: Arc<Semaphore>,
+    admitted: OwnedSemaphorePermit
+}
+
+impl Admission {
+
+    pub async fn run<F, T>(self, f: F) -> Result<T, Status>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static {
+
+        let admitted = self.admitted;
+        let running = match self.running.acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => return Err(Status::unavailable("Worker pool is closed"))
+        };
+
+        // Permits are released by the job itself, so a dropped request doesn't free
+        // the slot while its job still runs
+        let job = tokio::task::spawn_blocking(move || {
+            let _permits = (admitted, running);
+            f()
+        });
+
+        match job.await {
+            Ok(result) => Ok(result),
+            Err(err) => {
+                log::error!("Worker job failed: {:?}", err);
+                Err(Status::internal("Internal error"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[tokio::test]
+    async fn reject_when_full() {
+        let pool = WorkerPool::new(1, 0);
+        let (tx, rx) = mpsc::channel::<()>();
+
+        let busy = pool.clone();
+        let first = tokio::spawn(async move {
+            busy.run(move || rx.recv().is_ok()).await
+        });
+        while pool.admitted.available_permits() > 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let rejected = pool.run(|| true).await;
+        assert_eq!(rejected.unwrap_err().code(), tonic::Code::ResourceExhausted);
+
+        tx.send(()).unwrap();
+        assert!(first.await.unwrap().unwrap());
+        assert!(pool.run(|| true).await.unwrap());
+    }
+}